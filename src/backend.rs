@@ -0,0 +1,62 @@
+use crate::desktop::DesktopInfo;
+use std::fmt::Debug;
+use std::sync::mpsc::Sender;
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(windows)]
+pub mod win;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+/// Some events are only sent by some backends, e.g. only Windows lets desktops be moved.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[allow(unused)]
+pub enum DesktopEvent {
+    DesktopCreated,
+    DesktopDestroyed,
+    DesktopChanged,
+    DesktopNameChanged { index: u32 },
+    DesktopMoved,
+    Other,
+}
+
+pub trait VirtualDesktopBackend: Clone + Send + Sync + 'static {
+    type Error: Debug + Send + 'static;
+    type EventListener: Send + Sync + 'static;
+
+    /// Starts forwarding desktop events to `sender` for as long as the returned listener lives.
+    fn listen_desktop_events(
+        &self,
+        sender: Sender<DesktopEvent>,
+    ) -> Result<Self::EventListener, Self::Error>;
+
+    fn get_current_desktop(&self) -> Result<DesktopInfo, Self::Error>;
+
+    fn get_desktops(&self) -> Result<Vec<DesktopInfo>, Self::Error>;
+
+    fn switch_desktop(&self, index: u32) -> Result<(), Self::Error>;
+
+    fn move_active_window_to_desktop(&self, index: u32) -> Result<(), Self::Error>;
+
+    fn rename_desktop(&self, index: u32, name: &str) -> Result<(), Self::Error>;
+
+    /// Adds a desktop after the last one, returning its index.
+    fn create_desktop(&self) -> Result<u32, Self::Error>;
+
+    /// Removes a desktop, moving its windows to the desktop at `fallback_index`.
+    fn remove_desktop(&self, index: u32, fallback_index: u32) -> Result<(), Self::Error>;
+
+    /// Gives input focus to a window on the current desktop, if there is one.
+    fn focus_window_on_current_desktop(&self) -> Result<(), Self::Error>;
+}
+
+#[cfg(windows)]
+pub fn platform_backend() -> Result<win::WindowsBackend, win::WindowsBackendError> {
+    Ok(win::WindowsBackend)
+}
+
+#[cfg(target_os = "linux")]
+pub fn platform_backend() -> Result<x11::X11Backend, x11::X11BackendError> {
+    x11::X11Backend::new()
+}
@@ -0,0 +1,150 @@
+use crate::backend::{DesktopEvent, VirtualDesktopBackend};
+use crate::desktop::DesktopInfo;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// In-memory backend whose desktops and events are driven by the caller.
+#[derive(Clone, Debug, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeState {
+    pub desktop_names: Vec<String>,
+    pub current_index: u32,
+    pub window_moves: Vec<u32>,
+    pub focus_requests: usize,
+    /// Makes every switch fail, like a desktop that went away in the meantime.
+    pub fail_switches: bool,
+    listeners: Vec<Sender<DesktopEvent>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FakeBackendError {
+    DesktopIndexNotFound(u32),
+    SwitchFailed,
+}
+
+impl FakeBackend {
+    pub fn new(desktop_names: &[&str]) -> Self {
+        let backend = Self::default();
+        backend.state().desktop_names = desktop_names.iter().map(|it| it.to_string()).collect();
+        backend
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Sends `event` to every listener without touching the desktop state.
+    pub fn emit(&self, event: DesktopEvent) {
+        self.state()
+            .listeners
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+}
+
+impl FakeState {
+    fn desktop(&self, index: u32) -> Result<DesktopInfo, FakeBackendError> {
+        match self.desktop_names.get(index as usize) {
+            Some(name) => Ok(DesktopInfo {
+                name: name.clone(),
+                index,
+                guid: None,
+            }),
+            None => Err(FakeBackendError::DesktopIndexNotFound(index)),
+        }
+    }
+}
+
+impl VirtualDesktopBackend for FakeBackend {
+    type Error = FakeBackendError;
+    type EventListener = ();
+
+    fn listen_desktop_events(
+        &self,
+        sender: Sender<DesktopEvent>,
+    ) -> Result<Self::EventListener, Self::Error> {
+        self.state().listeners.push(sender);
+        Ok(())
+    }
+
+    fn get_current_desktop(&self) -> Result<DesktopInfo, Self::Error> {
+        let state = self.state();
+        state.desktop(state.current_index)
+    }
+
+    fn get_desktops(&self) -> Result<Vec<DesktopInfo>, Self::Error> {
+        let state = self.state();
+        (0..state.desktop_names.len() as u32)
+            .map(|index| state.desktop(index))
+            .collect()
+    }
+
+    fn switch_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        {
+            let mut state = self.state();
+            state.desktop(index)?;
+            if state.fail_switches {
+                return Err(FakeBackendError::SwitchFailed);
+            }
+            state.current_index = index;
+        }
+        self.emit(DesktopEvent::DesktopChanged);
+        Ok(())
+    }
+
+    fn move_active_window_to_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        let mut state = self.state();
+        state.desktop(index)?;
+        state.window_moves.push(index);
+        Ok(())
+    }
+
+    fn rename_desktop(&self, index: u32, name: &str) -> Result<(), Self::Error> {
+        {
+            let mut state = self.state();
+            let Some(desktop_name) = state.desktop_names.get_mut(index as usize) else {
+                return Err(FakeBackendError::DesktopIndexNotFound(index));
+            };
+            *desktop_name = name.to_string();
+        }
+        self.emit(DesktopEvent::DesktopNameChanged { index });
+        Ok(())
+    }
+
+    fn create_desktop(&self) -> Result<u32, Self::Error> {
+        let index = {
+            let mut state = self.state();
+            let index = state.desktop_names.len() as u32;
+            state.desktop_names.push(format!("Desktop {}", index + 1));
+            index
+        };
+        self.emit(DesktopEvent::DesktopCreated);
+        Ok(index)
+    }
+
+    fn remove_desktop(&self, index: u32, fallback_index: u32) -> Result<(), Self::Error> {
+        {
+            let mut state = self.state();
+            state.desktop(index)?;
+            state.desktop(fallback_index)?;
+            state.desktop_names.remove(index as usize);
+            // Indices after the removed desktop shift down by one
+            let shift = |it: u32| if it > index { it - 1 } else { it };
+            state.current_index = if state.current_index == index {
+                shift(fallback_index)
+            } else {
+                shift(state.current_index)
+            };
+        }
+        self.emit(DesktopEvent::DesktopDestroyed);
+        Ok(())
+    }
+
+    fn focus_window_on_current_desktop(&self) -> Result<(), Self::Error> {
+        self.state().focus_requests += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use crate::backend::{DesktopEvent, VirtualDesktopBackend};
+use crate::desktop::DesktopInfo;
+use crate::guard_clause;
+use std::ffi::c_void;
+use std::sync::mpsc::Sender;
+use std::{sync, thread};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::WindowsAndMessaging::SetForegroundWindow;
+use winvd::{
+    create_desktop, get_current_desktop, get_desktops, is_window_on_current_desktop,
+    listen_desktop_events, move_window_to_desktop, remove_desktop, switch_desktop, Desktop,
+    DesktopEventThread, Error,
+};
+use x_win::{get_active_window, get_open_windows, WindowInfo};
+
+#[derive(Clone, Debug, Default)]
+pub struct WindowsBackend;
+
+pub struct WindowsEventListener {
+    _listener_thread: DesktopEventThread,
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+pub enum WindowsBackendError {
+    VirtualDesktopError(Error),
+    WindowInfoError(String),
+    DesktopIndexNotFound(u32),
+    SetForegroundWindowFailed,
+}
+
+impl From<Error> for WindowsBackendError {
+    fn from(error: Error) -> Self {
+        Self::VirtualDesktopError(error)
+    }
+}
+
+fn desktop_info(desktop: Desktop) -> Result<DesktopInfo, Error> {
+    Ok(DesktopInfo {
+        name: desktop.get_name()?,
+        index: desktop.get_index()?,
+        guid: Some(format!("{:?}", desktop.get_id()?)),
+    })
+}
+
+fn target_desktop(index: u32) -> Result<Desktop, WindowsBackendError> {
+    let desktops = get_desktops()?;
+    match desktops.get(index as usize) {
+        Some(&desktop) => Ok(desktop),
+        None => Err(WindowsBackendError::DesktopIndexNotFound(index)),
+    }
+}
+
+impl From<winvd::DesktopEvent> for DesktopEvent {
+    fn from(event: winvd::DesktopEvent) -> Self {
+        match event {
+            winvd::DesktopEvent::DesktopCreated(_) => DesktopEvent::DesktopCreated,
+            winvd::DesktopEvent::DesktopDestroyed { .. } => DesktopEvent::DesktopDestroyed,
+            winvd::DesktopEvent::DesktopChanged { .. } => DesktopEvent::DesktopChanged,
+            winvd::DesktopEvent::DesktopNameChanged(desktop, _) => match desktop.get_index() {
+                Ok(index) => DesktopEvent::DesktopNameChanged { index },
+                Err(_) => DesktopEvent::Other,
+            },
+            winvd::DesktopEvent::DesktopMoved { .. } => DesktopEvent::DesktopMoved,
+            _ => DesktopEvent::Other,
+        }
+    }
+}
+
+impl VirtualDesktopBackend for WindowsBackend {
+    type Error = WindowsBackendError;
+    type EventListener = WindowsEventListener;
+
+    fn listen_desktop_events(
+        &self,
+        sender: Sender<DesktopEvent>,
+    ) -> Result<Self::EventListener, Self::Error> {
+        let (tx, rx) = sync::mpsc::channel::<winvd::DesktopEvent>();
+        let listener_thread = listen_desktop_events(tx)?;
+
+        // Ends once the listener thread is dropped, since that also drops the winvd sender
+        thread::spawn(move || {
+            for event in rx {
+                log::info!("Event received: {:?}", event);
+                if sender.send(event.into()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(WindowsEventListener {
+            _listener_thread: listener_thread,
+        })
+    }
+
+    fn get_current_desktop(&self) -> Result<DesktopInfo, Self::Error> {
+        Ok(desktop_info(get_current_desktop()?)?)
+    }
+
+    fn get_desktops(&self) -> Result<Vec<DesktopInfo>, Self::Error> {
+        let desktops: Result<Vec<_>, _> = get_desktops()?.into_iter().map(desktop_info).collect();
+        Ok(desktops?)
+    }
+
+    fn switch_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        Ok(switch_desktop(target_desktop(index)?)?)
+    }
+
+    fn move_active_window_to_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        let WindowInfo {
+            id: target_hwnd, ..
+        } = guard_clause!(get_active_window(), error, {
+            return Err(WindowsBackendError::WindowInfoError(format!("{:?}", error)));
+        });
+
+        let target_window = HWND(target_hwnd as *mut c_void);
+        Ok(move_window_to_desktop(
+            target_desktop(index)?,
+            &target_window,
+        )?)
+    }
+
+    fn rename_desktop(&self, index: u32, name: &str) -> Result<(), Self::Error> {
+        Ok(target_desktop(index)?.set_name(name)?)
+    }
+
+    fn create_desktop(&self) -> Result<u32, Self::Error> {
+        Ok(create_desktop()?.get_index()?)
+    }
+
+    fn remove_desktop(&self, index: u32, fallback_index: u32) -> Result<(), Self::Error> {
+        Ok(remove_desktop(
+            target_desktop(index)?,
+            target_desktop(fallback_index)?,
+        )?)
+    }
+
+    fn focus_window_on_current_desktop(&self) -> Result<(), Self::Error> {
+        let open_windows = guard_clause!(get_open_windows(), error, {
+            return Err(WindowsBackendError::WindowInfoError(format!("{:?}", error)));
+        });
+
+        let window_on_desktop = open_windows.into_iter().find(|window| {
+            // Minimized windows have negative coordinates
+            // Full screen windows also have negative coordinates (presumably padding?)
+            if !window.position.is_full_screen && (window.position.x <= 0 || window.position.y <= 0)
+            {
+                return false;
+            }
+            let window_handle = HWND(window.id as *mut c_void);
+            is_window_on_current_desktop(window_handle).unwrap_or(false)
+        });
+        let Some(target_window) = window_on_desktop else {
+            // Expected - Desktop probably has no open windows
+            return Ok(());
+        };
+
+        // Weird calling semantics...
+        if unsafe { SetForegroundWindow(HWND(target_window.id as *mut c_void)).0 } == 0 {
+            return Err(WindowsBackendError::SetForegroundWindowFailed);
+        }
+
+        log::info!("Set active window: {:?}", target_window);
+        Ok(())
+    }
+}
@@ -0,0 +1,648 @@
+use crate::backend::{DesktopEvent, VirtualDesktopBackend};
+use crate::desktop::DesktopInfo;
+use crate::guard_clause;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use x11rb::atom_manager;
+use x11rb::connection::Connection;
+use x11rb::errors::{ConnectError, ConnectionError, ReplyError};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt, EventMask,
+    PropMode, Window,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+atom_manager! {
+    pub Atoms: AtomsCookie {
+        _NET_CURRENT_DESKTOP,
+        _NET_NUMBER_OF_DESKTOPS,
+        _NET_DESKTOP_NAMES,
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_DESKTOP,
+        _NET_CLIENT_LIST,
+        _NET_CLIENT_LIST_STACKING,
+        UTF8_STRING,
+    }
+}
+
+/// Source indication for client messages, as defined by EWMH: 2 means "pager".
+const SOURCE_INDICATION_PAGER: u32 = 2;
+
+/// `_NET_WM_DESKTOP` of windows shown on every desktop.
+const ALL_DESKTOPS: u32 = 0xFFFFFFFF;
+
+/// How long to wait for the window manager to act on a request before giving up.
+const WINDOW_MANAGER_TIMEOUT: Duration = Duration::from_secs(1);
+const WINDOW_MANAGER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Desktop layer for X11 window managers implementing EWMH workspaces.
+#[derive(Clone)]
+pub struct X11Backend {
+    connection: Arc<RustConnection>,
+    root: Window,
+    atoms: Atoms,
+}
+
+pub struct X11EventListener {
+    _listener_thread: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+pub enum X11BackendError {
+    ConnectError(ConnectError),
+    ConnectionError(ConnectionError),
+    ReplyError(ReplyError),
+    MissingProperty(&'static str),
+    DesktopIndexNotFound(u32),
+    NoActiveWindow,
+    /// The window manager ignored the request, or didn't act on it in time.
+    WindowManagerTimeout,
+}
+
+impl From<ConnectError> for X11BackendError {
+    fn from(error: ConnectError) -> Self {
+        Self::ConnectError(error)
+    }
+}
+
+impl From<ConnectionError> for X11BackendError {
+    fn from(error: ConnectionError) -> Self {
+        Self::ConnectionError(error)
+    }
+}
+
+impl From<ReplyError> for X11BackendError {
+    fn from(error: ReplyError) -> Self {
+        Self::ReplyError(error)
+    }
+}
+
+/// Snapshot of the EWMH root window properties.
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct DesktopState {
+    current_index: u32,
+    count: u32,
+    names: Vec<String>,
+}
+
+impl DesktopState {
+    fn read(
+        connection: &RustConnection,
+        root: Window,
+        atoms: &Atoms,
+    ) -> Result<Self, X11BackendError> {
+        let Some(current_index) = read_cardinal(connection, root, atoms._NET_CURRENT_DESKTOP)?
+        else {
+            return Err(X11BackendError::MissingProperty("_NET_CURRENT_DESKTOP"));
+        };
+        let Some(count) = read_cardinal(connection, root, atoms._NET_NUMBER_OF_DESKTOPS)? else {
+            return Err(X11BackendError::MissingProperty("_NET_NUMBER_OF_DESKTOPS"));
+        };
+        let names = read_desktop_names(connection, root, atoms)?;
+        Ok(Self {
+            current_index,
+            count,
+            names,
+        })
+    }
+
+    fn desktop(&self, index: u32) -> DesktopInfo {
+        // _NET_DESKTOP_NAMES may list fewer names than there are desktops
+        let name = match self.names.get(index as usize) {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("Desktop {}", index + 1),
+        };
+        DesktopInfo {
+            name,
+            index,
+            guid: None,
+        }
+    }
+
+    /// Translates the difference between two snapshots into desktop events.
+    fn diff(&self, new: &DesktopState) -> Vec<DesktopEvent> {
+        let mut events = vec![];
+        if new.count > self.count {
+            events.push(DesktopEvent::DesktopCreated);
+        } else if new.count < self.count {
+            events.push(DesktopEvent::DesktopDestroyed);
+        }
+        if new.current_index != self.current_index {
+            events.push(DesktopEvent::DesktopChanged);
+        }
+        for index in 0..new.count.min(self.count) {
+            if self.desktop(index).name != new.desktop(index).name {
+                events.push(DesktopEvent::DesktopNameChanged { index });
+            }
+        }
+        events
+    }
+}
+
+fn read_cardinal(
+    connection: &RustConnection,
+    window: Window,
+    property: Atom,
+) -> Result<Option<u32>, X11BackendError> {
+    let reply = connection
+        .get_property(false, window, property, AtomEnum::CARDINAL, 0, 1)?
+        .reply()?;
+    Ok(reply.value32().and_then(|mut values| values.next()))
+}
+
+fn read_window_list(
+    connection: &RustConnection,
+    window: Window,
+    property: Atom,
+) -> Result<Vec<Window>, X11BackendError> {
+    let reply = connection
+        .get_property(false, window, property, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?;
+    Ok(reply
+        .value32()
+        .map(|values| values.collect())
+        .unwrap_or_default())
+}
+
+fn read_desktop_names(
+    connection: &RustConnection,
+    root: Window,
+    atoms: &Atoms,
+) -> Result<Vec<String>, X11BackendError> {
+    let reply = connection
+        .get_property(
+            false,
+            root,
+            atoms._NET_DESKTOP_NAMES,
+            atoms.UTF8_STRING,
+            0,
+            u32::MAX,
+        )?
+        .reply()?;
+    Ok(parse_desktop_names(&reply.value))
+}
+
+fn parse_desktop_names(value: &[u8]) -> Vec<String> {
+    let mut names: Vec<String> = value
+        .split(|&byte| byte == 0)
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect();
+    // Names should be null-terminated, leaving an empty split after the last one, but not every
+    // window manager writes the final null
+    if names.last().is_some_and(|name| name.is_empty()) {
+        names.pop();
+    }
+    names
+}
+
+impl X11Backend {
+    pub fn new() -> Result<Self, X11BackendError> {
+        let (connection, screen_num) = x11rb::connect(None)?;
+        let root = connection.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&connection)?.reply()?;
+        Ok(Self {
+            connection: Arc::new(connection),
+            root,
+            atoms,
+        })
+    }
+
+    fn state(&self) -> Result<DesktopState, X11BackendError> {
+        DesktopState::read(&self.connection, self.root, &self.atoms)
+    }
+
+    fn send_client_message(
+        &self,
+        window: Window,
+        message_type: Atom,
+        data: [u32; 5],
+    ) -> Result<(), X11BackendError> {
+        let event = ClientMessageEvent::new(32, window, message_type, data);
+        self.connection.send_event(
+            false,
+            self.root,
+            EventMask::SUBSTRUCTURE_NOTIFY | EventMask::SUBSTRUCTURE_REDIRECT,
+            event,
+        )?;
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    fn write_desktop_names(&self, names: &[String]) -> Result<(), X11BackendError> {
+        let value: Vec<u8> = names
+            .iter()
+            .flat_map(|name| name.bytes().chain([0]))
+            .collect();
+        self.connection.change_property8(
+            PropMode::REPLACE,
+            self.root,
+            self.atoms._NET_DESKTOP_NAMES,
+            self.atoms.UTF8_STRING,
+            &value,
+        )?;
+        self.connection.flush()?;
+        Ok(())
+    }
+
+    fn move_window(&self, window: Window, index: u32) -> Result<(), X11BackendError> {
+        self.send_client_message(
+            window,
+            self.atoms._NET_WM_DESKTOP,
+            [index, SOURCE_INDICATION_PAGER, 0, 0, 0],
+        )
+    }
+
+    fn existing_index(&self, index: u32) -> Result<u32, X11BackendError> {
+        if index >= self.state()?.count {
+            return Err(X11BackendError::DesktopIndexNotFound(index));
+        }
+        Ok(index)
+    }
+}
+
+impl VirtualDesktopBackend for X11Backend {
+    type Error = X11BackendError;
+    type EventListener = X11EventListener;
+
+    fn listen_desktop_events(
+        &self,
+        sender: Sender<DesktopEvent>,
+    ) -> Result<Self::EventListener, Self::Error> {
+        // Waiting for events on a separate connection keeps replies on the shared one unaffected
+        let (connection, _) = x11rb::connect(None)?;
+        let root = self.root;
+        let atoms = self.atoms;
+
+        connection.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        connection.flush()?;
+        let mut last_state = DesktopState::read(&connection, root, &atoms)?;
+
+        let listener_thread = thread::spawn(move || loop {
+            let event = guard_clause!(connection.wait_for_event(), error, {
+                log::error!("Lost connection to the X server: {:?}", error);
+                break;
+            });
+            let Event::PropertyNotify(event) = event else {
+                continue;
+            };
+            if event.window != root
+                || ![
+                    atoms._NET_CURRENT_DESKTOP,
+                    atoms._NET_NUMBER_OF_DESKTOPS,
+                    atoms._NET_DESKTOP_NAMES,
+                ]
+                .contains(&event.atom)
+            {
+                continue;
+            }
+
+            let state = guard_clause!(DesktopState::read(&connection, root, &atoms), error, {
+                log::error!("Could not read desktop state: {:?}", error);
+                continue;
+            });
+            for desktop_event in last_state.diff(&state) {
+                log::debug!("Event received: {:?}", desktop_event);
+                if sender.send(desktop_event).is_err() {
+                    return;
+                }
+            }
+            last_state = state;
+        });
+
+        Ok(X11EventListener {
+            _listener_thread: listener_thread,
+        })
+    }
+
+    fn get_current_desktop(&self) -> Result<DesktopInfo, Self::Error> {
+        let state = self.state()?;
+        Ok(state.desktop(state.current_index))
+    }
+
+    fn get_desktops(&self) -> Result<Vec<DesktopInfo>, Self::Error> {
+        let state = self.state()?;
+        Ok((0..state.count).map(|index| state.desktop(index)).collect())
+    }
+
+    fn switch_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        let index = self.existing_index(index)?;
+        self.send_client_message(
+            self.root,
+            self.atoms._NET_CURRENT_DESKTOP,
+            [index, x11rb::CURRENT_TIME, 0, 0, 0],
+        )
+    }
+
+    fn move_active_window_to_desktop(&self, index: u32) -> Result<(), Self::Error> {
+        let index = self.existing_index(index)?;
+        let active_window =
+            read_window_list(&self.connection, self.root, self.atoms._NET_ACTIVE_WINDOW)?
+                .into_iter()
+                .find(|&window| window != x11rb::NONE);
+        let Some(active_window) = active_window else {
+            return Err(X11BackendError::NoActiveWindow);
+        };
+        self.move_window(active_window, index)
+    }
+
+    fn rename_desktop(&self, index: u32, name: &str) -> Result<(), Self::Error> {
+        let state = self.state()?;
+        if index >= state.count {
+            return Err(X11BackendError::DesktopIndexNotFound(index));
+        }
+        // Desktops without a name yet are padded with empty names, which window managers treat as unnamed
+        let mut names = state.names;
+        if names.len() <= index as usize {
+            names.resize(index as usize + 1, String::new());
+        }
+        names[index as usize] = name.to_string();
+        self.write_desktop_names(&names)
+    }
+
+    fn create_desktop(&self) -> Result<u32, Self::Error> {
+        let count = self.state()?.count;
+        self.send_client_message(
+            self.root,
+            self.atoms._NET_NUMBER_OF_DESKTOPS,
+            [count + 1, 0, 0, 0, 0],
+        )?;
+
+        // The window manager adds the desktop asynchronously, and callers act on it right away
+        let deadline = Instant::now() + WINDOW_MANAGER_TIMEOUT;
+        while self.state()?.count <= count {
+            if Instant::now() >= deadline {
+                return Err(X11BackendError::WindowManagerTimeout);
+            }
+            thread::sleep(WINDOW_MANAGER_POLL_INTERVAL);
+        }
+        Ok(count)
+    }
+
+    /// EWMH can only change the number of desktops, which drops the last one. Any other desktop is
+    /// removed by first moving the windows and names of the desktops after it down by one.
+    fn remove_desktop(&self, index: u32, fallback_index: u32) -> Result<(), Self::Error> {
+        let state = self.state()?;
+        let index = self.existing_index(index)?;
+        let fallback_index = self.existing_index(fallback_index)?;
+        // Where a desktop ends up once the removed one is gone
+        let shifted = |other: u32| match other {
+            _ if other == index => fallback_index - (fallback_index > index) as u32,
+            _ if other > index => other - 1,
+            _ => other,
+        };
+
+        let windows = read_window_list(&self.connection, self.root, self.atoms._NET_CLIENT_LIST)?;
+        for window in windows {
+            let desktop = read_cardinal(&self.connection, window, self.atoms._NET_WM_DESKTOP)?;
+            match desktop {
+                Some(desktop) if desktop != ALL_DESKTOPS && shifted(desktop) != desktop => {
+                    self.move_window(window, shifted(desktop))?
+                }
+                _ => {}
+            }
+        }
+        if (index as usize) < state.names.len() {
+            let mut names = state.names;
+            names.remove(index as usize);
+            self.write_desktop_names(&names)?;
+        }
+        if shifted(state.current_index) != state.current_index {
+            self.switch_desktop(shifted(state.current_index))?;
+        }
+        self.send_client_message(
+            self.root,
+            self.atoms._NET_NUMBER_OF_DESKTOPS,
+            [state.count - 1, 0, 0, 0, 0],
+        )
+    }
+
+    fn focus_window_on_current_desktop(&self) -> Result<(), Self::Error> {
+        let current_index = self.state()?.current_index;
+        let windows = read_window_list(
+            &self.connection,
+            self.root,
+            self.atoms._NET_CLIENT_LIST_STACKING,
+        )?;
+
+        // Stacking order is bottom to top, so search from the topmost window down
+        for window in windows.into_iter().rev() {
+            let desktop = read_cardinal(&self.connection, window, self.atoms._NET_WM_DESKTOP)?;
+            if desktop != Some(current_index) {
+                continue;
+            }
+            return self.send_client_message(
+                window,
+                self.atoms._NET_ACTIVE_WINDOW,
+                [SOURCE_INDICATION_PAGER, x11rb::CURRENT_TIME, 0, 0, 0],
+            );
+        }
+
+        // Expected - Desktop probably has no open windows
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+    use x11rb::protocol::xproto::{CreateWindowAux, WindowClass};
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    fn state(current_index: u32, names: &[&str]) -> DesktopState {
+        DesktopState {
+            current_index,
+            count: names.len() as u32,
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn reads_names_with_or_without_the_last_null() {
+        let cases: [(&[u8], &[&str]); 5] = [
+            (b"", &[]),
+            (b"One\0Two\0", &["One", "Two"]),
+            (b"One\0Two", &["One", "Two"]),
+            (b"One\0\0Three\0", &["One", "", "Three"]),
+            (b"\0\0", &["", ""]),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_desktop_names(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn turns_property_changes_into_events() {
+        let before = state(0, &["One", "Two", ""]);
+        let cases = [
+            (state(0, &["One", "Two", ""]), vec![]),
+            (
+                state(2, &["One", "Two", ""]),
+                vec![DesktopEvent::DesktopChanged],
+            ),
+            (
+                state(0, &["One", "Work", "Desktop 3"]),
+                vec![DesktopEvent::DesktopNameChanged { index: 1 }],
+            ),
+            (
+                state(0, &["One", "Two", "", ""]),
+                vec![DesktopEvent::DesktopCreated],
+            ),
+            (
+                state(1, &["Two", "Three"]),
+                vec![
+                    DesktopEvent::DesktopDestroyed,
+                    DesktopEvent::DesktopChanged,
+                    DesktopEvent::DesktopNameChanged { index: 0 },
+                    DesktopEvent::DesktopNameChanged { index: 1 },
+                ],
+            ),
+        ];
+        for (after, expected) in cases {
+            assert_eq!(before.diff(&after), expected, "{:?}", after);
+        }
+    }
+
+    /// Acts on the requests of the backend like an EWMH window manager would.
+    fn run_window_manager(atoms: Atoms) {
+        let (connection, screen_num) = x11rb::connect(None).unwrap();
+        let root = connection.setup().roots[screen_num].root;
+        connection
+            .change_window_attributes(
+                root,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::SUBSTRUCTURE_REDIRECT),
+            )
+            .unwrap()
+            .check()
+            .expect("Another window manager is running");
+
+        thread::spawn(move || {
+            while let Ok(event) = connection.wait_for_event() {
+                let Event::ClientMessage(event) = event else {
+                    continue;
+                };
+                let value = event.data.as_data32()[0];
+                let (window, property) = match event.type_ {
+                    atom if atom == atoms._NET_WM_DESKTOP => (event.window, atom),
+                    atom => (root, atom),
+                };
+                connection
+                    .change_property32(
+                        PropMode::REPLACE,
+                        window,
+                        property,
+                        AtomEnum::CARDINAL,
+                        &[value],
+                    )
+                    .unwrap();
+                connection.flush().unwrap();
+            }
+        });
+    }
+
+    fn wait_for(events: &Receiver<DesktopEvent>, expected: DesktopEvent) {
+        loop {
+            match events.recv_timeout(WINDOW_MANAGER_TIMEOUT) {
+                Ok(event) if event == expected => return,
+                Ok(_) => continue,
+                Err(error) => panic!("No {:?} event: {:?}", expected, error),
+            }
+        }
+    }
+
+    /// Needs an X server without a window manager, e.g. `xvfb-run cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn manages_desktops_on_an_x_server() {
+        let backend = X11Backend::new().unwrap();
+        let (connection, root, atoms) = (&backend.connection, backend.root, backend.atoms);
+        let set_cardinal = |window: Window, property: Atom, value: u32| {
+            connection
+                .change_property32(
+                    PropMode::REPLACE,
+                    window,
+                    property,
+                    AtomEnum::CARDINAL,
+                    &[value],
+                )
+                .unwrap();
+        };
+        set_cardinal(root, atoms._NET_NUMBER_OF_DESKTOPS, 3);
+        set_cardinal(root, atoms._NET_CURRENT_DESKTOP, 0);
+        backend
+            .write_desktop_names(&["One".to_string(), "Two".to_string(), "Three".to_string()])
+            .unwrap();
+        run_window_manager(atoms);
+
+        let (sender, events) = mpsc::channel();
+        let _listener = backend.listen_desktop_events(sender).unwrap();
+        let names = || -> Vec<String> {
+            let desktops = backend.get_desktops().unwrap();
+            desktops.into_iter().map(|info| info.name).collect()
+        };
+
+        backend.switch_desktop(2).unwrap();
+        wait_for(&events, DesktopEvent::DesktopChanged);
+        assert_eq!(backend.get_current_desktop().unwrap().name, "Three");
+
+        backend.rename_desktop(1, "Work").unwrap();
+        wait_for(&events, DesktopEvent::DesktopNameChanged { index: 1 });
+        assert_eq!(names(), ["One", "Work", "Three"]);
+
+        assert_eq!(backend.create_desktop().unwrap(), 3);
+        wait_for(&events, DesktopEvent::DesktopCreated);
+        assert_eq!(names(), ["One", "Work", "Three", "Desktop 4"]);
+
+        // One window on the last desktop and one shown on all of them
+        let windows: Vec<Window> = (0..2)
+            .map(|_| {
+                let window = connection.generate_id().unwrap();
+                connection
+                    .create_window(
+                        COPY_DEPTH_FROM_PARENT,
+                        window,
+                        root,
+                        0,
+                        0,
+                        1,
+                        1,
+                        0,
+                        WindowClass::INPUT_OUTPUT,
+                        0,
+                        &CreateWindowAux::new(),
+                    )
+                    .unwrap();
+                window
+            })
+            .collect();
+        set_cardinal(windows[0], atoms._NET_WM_DESKTOP, 3);
+        set_cardinal(windows[1], atoms._NET_WM_DESKTOP, ALL_DESKTOPS);
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                root,
+                atoms._NET_CLIENT_LIST,
+                AtomEnum::WINDOW,
+                &windows,
+            )
+            .unwrap();
+        connection.flush().unwrap();
+
+        backend.remove_desktop(1, 0).unwrap();
+        wait_for(&events, DesktopEvent::DesktopDestroyed);
+        assert_eq!(names(), ["One", "Three", "Desktop 3"]);
+        assert_eq!(backend.get_current_desktop().unwrap().name, "Three");
+        let desktop_of = |window| read_cardinal(connection, window, atoms._NET_WM_DESKTOP);
+        assert_eq!(desktop_of(windows[0]).unwrap(), Some(2));
+        assert_eq!(desktop_of(windows[1]).unwrap(), Some(ALL_DESKTOPS));
+    }
+}
@@ -0,0 +1,569 @@
+use crate::config::{read_migrated, ConfigLocation, Settings, SettingsError};
+use crate::format::ConfigFormat;
+use crate::guard_clause;
+use crate::migration::CURRENT_VERSION;
+use crate::schema::settings_schema;
+use crate::validation::ValidationIssue;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+pub const USAGE: &str = "\
+Usage:
+  DesktopIndicator [<CONFIG OPTIONS>]
+      Start the tray application.
+  DesktopIndicator validate [<CONFIG OPTIONS>] [--json]
+      Check the settings file and report every problem found, without starting the tray.
+      `--check-config` is accepted as an alias for `validate`.
+  DesktopIndicator convert --to <yaml|toml|json> [--config <PATH>] [--output <PATH>]
+      Rewrite the settings file in another format. Comments are not carried over.
+      Writes next to the settings file with the new extension unless `--output` is given.
+  DesktopIndicator migrate [--config <PATH>] [--write]
+      Upgrade the settings file to the current layout version and print the result.
+      With `--write`, the file is replaced and the original is kept with a `.bak` suffix,
+      numbered if an earlier backup exists.
+  DesktopIndicator --print-default-config
+      Print an annotated settings file containing every default value.
+  DesktopIndicator --print-schema
+      Print the JSON Schema of the settings file, e.g. for editor completion.
+  DesktopIndicator --help
+      Show this message.
+
+Config options:
+  --config <PATH>       Settings file to use instead of searching the default locations.
+                        Can also be set with DESKTOP_INDICATOR_CONFIG.
+  --base-config <PATH>  Shared settings file that the settings file is layered on top of.
+                        Can also be set with DESKTOP_INDICATOR_BASE_CONFIG.";
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ConfigArgs {
+    pub config_path: Option<PathBuf>,
+    pub base_config_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Command {
+    Run {
+        config: ConfigArgs,
+    },
+    Validate {
+        config: ConfigArgs,
+        format: ReportFormat,
+    },
+    Convert {
+        config: ConfigArgs,
+        format: ConfigFormat,
+        output_path: Option<PathBuf>,
+    },
+    Migrate {
+        config: ConfigArgs,
+        write: bool,
+    },
+    PrintDefaultConfig,
+    PrintSchema,
+    Help,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum CliError {
+    MissingValue(String),
+    MissingArgument(String),
+    UnknownArgument(String),
+    UnknownFormat(String),
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Subcommand {
+    Run,
+    Validate,
+    Convert,
+    Migrate,
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, arg: &str) -> Result<String, CliError> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => Err(CliError::MissingValue(arg.to_string())),
+    }
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut subcommand = Subcommand::Run;
+        let mut config = ConfigArgs::default();
+        let mut json = false;
+        let mut format = None;
+        let mut output_path = None;
+        let mut write = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match (subcommand, arg.as_str()) {
+                (Subcommand::Run, "validate" | "--check-config") => {
+                    subcommand = Subcommand::Validate
+                }
+                (Subcommand::Run, "convert") => subcommand = Subcommand::Convert,
+                (Subcommand::Run, "migrate") => subcommand = Subcommand::Migrate,
+                (Subcommand::Validate, "--json") => json = true,
+                (Subcommand::Convert, "--to") => {
+                    let name = next_value(&mut args, &arg)?;
+                    let Some(parsed_format) = ConfigFormat::from_name(&name) else {
+                        return Err(CliError::UnknownFormat(name));
+                    };
+                    format = Some(parsed_format);
+                }
+                (Subcommand::Migrate, "--write") => write = true,
+                (Subcommand::Convert, "--output") => {
+                    output_path = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                (_, "--config") => {
+                    config.config_path = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                (_, "--base-config") => {
+                    config.base_config_path = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                (_, "--print-default-config") => return Ok(Command::PrintDefaultConfig),
+                (_, "--print-schema") => return Ok(Command::PrintSchema),
+                (_, "-h" | "--help") => return Ok(Command::Help),
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+
+        Ok(match subcommand {
+            Subcommand::Run => Command::Run { config },
+            Subcommand::Validate => Command::Validate {
+                config,
+                format: if json {
+                    ReportFormat::Json
+                } else {
+                    ReportFormat::Text
+                },
+            },
+            Subcommand::Convert => {
+                let Some(format) = format else {
+                    return Err(CliError::MissingArgument("--to".to_string()));
+                };
+                Command::Convert {
+                    config,
+                    format,
+                    output_path,
+                }
+            }
+            Subcommand::Migrate => Command::Migrate { config, write },
+        })
+    }
+}
+
+impl ConfigArgs {
+    pub fn resolve(self) -> Result<ConfigLocation, SettingsError> {
+        ConfigLocation::resolve(self.config_path, self.base_config_path)
+    }
+}
+
+#[derive(Serialize)]
+struct ValidationReport {
+    config_path: Option<String>,
+    valid: bool,
+    error: Option<String>,
+    issues: Vec<ValidationIssue>,
+}
+
+/// Loads and validates the settings, printing a report to stdout.
+pub fn validate(config: ConfigArgs, format: ReportFormat) -> ExitCode {
+    let (config_path, result) = match config.resolve() {
+        Ok(location) => (
+            Some(location.primary.display().to_string()),
+            Settings::load_with_warnings(&location).map(|(_, warnings)| warnings),
+        ),
+        Err(error) => (None, Err(error)),
+    };
+
+    // Unlike at startup, warnings fail the check too
+    let (error, issues) = match result {
+        Ok(warnings) => (None, warnings),
+        Err(SettingsError::InvalidSettings(issues)) => (None, issues),
+        Err(error) => (Some(error.to_string()), vec![]),
+    };
+    let report = ValidationReport {
+        config_path,
+        valid: error.is_none() && issues.is_empty(),
+        error,
+        issues,
+    };
+
+    match format {
+        ReportFormat::Json => match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(error) => {
+                eprintln!("Failed to serialize validation report: {}", error);
+                return ExitCode::FAILURE;
+            }
+        },
+        ReportFormat::Text => print_text_report(&report),
+    }
+
+    if report.valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn print_text_report(report: &ValidationReport) {
+    let config_path = report.config_path.as_deref().unwrap_or("<unknown>");
+    if report.valid {
+        println!("{}: OK", config_path);
+        return;
+    }
+    if let Some(error) = &report.error {
+        println!("{}: {}", config_path, error);
+    }
+    for issue in &report.issues {
+        println!("{}: {}", config_path, issue);
+    }
+}
+
+/// Rewrites the primary settings file in another format.
+pub fn convert(config: ConfigArgs, format: ConfigFormat, output_path: Option<PathBuf>) -> ExitCode {
+    let location = guard_clause!(config.resolve(), error, {
+        eprintln!("Could not locate settings: {}", error);
+        return ExitCode::FAILURE;
+    });
+    let settings = guard_clause!(Settings::read_file(&location.primary), error, {
+        eprintln!("Could not read {}: {}", location.primary.display(), error);
+        return ExitCode::FAILURE;
+    });
+    let contents = guard_clause!(format.serialize(&settings), error, {
+        eprintln!("Could not convert settings: {:?}", error);
+        return ExitCode::FAILURE;
+    });
+
+    let output_path =
+        output_path.unwrap_or_else(|| location.primary.with_extension(format.extension()));
+    if output_path.exists() {
+        eprintln!(
+            "Refusing to overwrite existing file {}",
+            output_path.display()
+        );
+        return ExitCode::FAILURE;
+    }
+    if let Err(error) = fs::write(&output_path, contents) {
+        eprintln!("Could not write {}: {}", output_path.display(), error);
+        return ExitCode::FAILURE;
+    }
+
+    println!(
+        "Converted {} to {}",
+        location.primary.display(),
+        output_path.display()
+    );
+    ExitCode::SUCCESS
+}
+
+/// Upgrades the primary settings file to the current layout, printing it or writing it back.
+pub fn migrate(config: ConfigArgs, write: bool) -> ExitCode {
+    let location = guard_clause!(config.resolve(), error, {
+        eprintln!("Could not locate settings: {}", error);
+        return ExitCode::FAILURE;
+    });
+    let path = &location.primary;
+    let Some(format) = ConfigFormat::from_path(path) else {
+        eprintln!("Unsupported settings file extension: {}", path.display());
+        return ExitCode::FAILURE;
+    };
+    let (settings, original_version) = guard_clause!(read_migrated(path), error, {
+        eprintln!("Could not read {}: {}", path.display(), error);
+        return ExitCode::FAILURE;
+    });
+    // Only the layout changes, the fields the user left out stay out
+    let contents = guard_clause!(format.serialize(&settings), error, {
+        eprintln!("Could not serialize settings: {:?}", error);
+        return ExitCode::FAILURE;
+    });
+
+    if !write {
+        print!("{}", contents);
+        return ExitCode::SUCCESS;
+    }
+    let Some(original_version) = original_version else {
+        println!(
+            "{} already uses the layout of version {}",
+            path.display(),
+            CURRENT_VERSION
+        );
+        return ExitCode::SUCCESS;
+    };
+
+    let backup_path = guard_clause!(back_up(path), error, {
+        eprintln!("Could not back up {}: {}", path.display(), error);
+        return ExitCode::FAILURE;
+    });
+    if let Err(error) = fs::write(path, contents) {
+        eprintln!("Could not write {}: {}", path.display(), error);
+        return ExitCode::FAILURE;
+    }
+
+    println!(
+        "Migrated {} from version {} to {}, the original was saved to {}",
+        path.display(),
+        original_version,
+        CURRENT_VERSION,
+        backup_path.display()
+    );
+    ExitCode::SUCCESS
+}
+
+fn backup_path(path: &Path, number: u32) -> PathBuf {
+    let mut backup_path = path.as_os_str().to_os_string();
+    backup_path.push(".bak");
+    if number > 1 {
+        backup_path.push(format!(".{}", number));
+    }
+    PathBuf::from(backup_path)
+}
+
+/// The number of the newest backup of `path`, counting `<path>.bak` as 1, or 0 if there is none.
+fn newest_backup_number(path: &Path) -> io::Result<u32> {
+    let Some(prefix) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(0);
+    };
+    let prefix = format!("{}.bak", prefix);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut newest = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some(suffix) = name.to_str().and_then(|name| name.strip_prefix(&prefix)) else {
+            continue;
+        };
+        let number = match suffix.strip_prefix('.') {
+            Some(number) => number.parse().ok(),
+            None => suffix.is_empty().then_some(1),
+        };
+        newest = newest.max(number.unwrap_or(0));
+    }
+    Ok(newest)
+}
+
+/// Copies `path` to `<path>.bak`, or `<path>.bak.2` and so on if earlier backups exist, as those
+/// may be the only copies of even older files. The new backup always gets the highest number.
+fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let contents = fs::read(path)?;
+    for number in newest_backup_number(path)? + 1.. {
+        let backup_path = backup_path(path, number);
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup_path)
+        {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        };
+        file.write_all(&contents)?;
+        return Ok(backup_path);
+    }
+    unreachable!("Ran out of backup file names")
+}
+
+/// Lets the output of the commands reach the terminal they were started from, which the tray
+/// application doesn't get as it's built without a console of its own.
+#[cfg(windows)]
+pub fn attach_console() {
+    use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // Fails when there is no parent console, e.g. when started from Explorer, which is fine
+    let _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+#[cfg(not(windows))]
+pub fn attach_console() {}
+
+pub fn print_schema() -> ExitCode {
+    match serde_json::to_string_pretty(&settings_schema()) {
+        Ok(json) => {
+            println!("{}", json);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Failed to serialize schema: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, CliError> {
+        Command::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    fn config(config_path: Option<&str>, base_config_path: Option<&str>) -> ConfigArgs {
+        ConfigArgs {
+            config_path: config_path.map(PathBuf::from),
+            base_config_path: base_config_path.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let cases = [
+            (
+                "",
+                Command::Run {
+                    config: config(None, None),
+                },
+            ),
+            (
+                "--config a.yaml --base-config base.yaml",
+                Command::Run {
+                    config: config(Some("a.yaml"), Some("base.yaml")),
+                },
+            ),
+            (
+                "validate",
+                Command::Validate {
+                    config: config(None, None),
+                    format: ReportFormat::Text,
+                },
+            ),
+            (
+                "--check-config --json --config a.yaml",
+                Command::Validate {
+                    config: config(Some("a.yaml"), None),
+                    format: ReportFormat::Json,
+                },
+            ),
+            (
+                "convert --to TOML --output b.toml",
+                Command::Convert {
+                    config: config(None, None),
+                    format: ConfigFormat::Toml,
+                    output_path: Some(PathBuf::from("b.toml")),
+                },
+            ),
+            (
+                "convert --config a.yaml --to yml",
+                Command::Convert {
+                    config: config(Some("a.yaml"), None),
+                    format: ConfigFormat::Yaml,
+                    output_path: None,
+                },
+            ),
+            (
+                "migrate",
+                Command::Migrate {
+                    config: config(None, None),
+                    write: false,
+                },
+            ),
+            (
+                "migrate --write --config a.json",
+                Command::Migrate {
+                    config: config(Some("a.json"), None),
+                    write: true,
+                },
+            ),
+            ("--print-default-config", Command::PrintDefaultConfig),
+            ("--print-schema", Command::PrintSchema),
+            ("validate --print-schema", Command::PrintSchema),
+            ("-h", Command::Help),
+            ("migrate --help", Command::Help),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args), Ok(expected), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let error = |error: fn(String) -> CliError, arg: &str| Err(error(arg.to_string()));
+        let cases = [
+            ("--verbose", error(CliError::UnknownArgument, "--verbose")),
+            (
+                "settings.yaml",
+                error(CliError::UnknownArgument, "settings.yaml"),
+            ),
+            (
+                "validate migrate",
+                error(CliError::UnknownArgument, "migrate"),
+            ),
+            // Options only apply to their own subcommand
+            ("--json", error(CliError::UnknownArgument, "--json")),
+            ("migrate --json", error(CliError::UnknownArgument, "--json")),
+            (
+                "validate --write",
+                error(CliError::UnknownArgument, "--write"),
+            ),
+            (
+                "convert --write --to json",
+                error(CliError::UnknownArgument, "--write"),
+            ),
+            ("--config", error(CliError::MissingValue, "--config")),
+            (
+                "validate --base-config",
+                error(CliError::MissingValue, "--base-config"),
+            ),
+            ("convert --to", error(CliError::MissingValue, "--to")),
+            (
+                "convert --to json --output",
+                error(CliError::MissingValue, "--output"),
+            ),
+            ("convert --to xml", error(CliError::UnknownFormat, "xml")),
+            ("convert", error(CliError::MissingArgument, "--to")),
+            (
+                "convert --output b.json",
+                error(CliError::MissingArgument, "--to"),
+            ),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args), expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn numbers_backups_after_the_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.yaml");
+        let backup = |name: &str| dir.path().join(name);
+
+        let mut backups = vec![];
+        for version in 1..=3 {
+            fs::write(&path, format!("version: {}", version)).unwrap();
+            backups.push(back_up(&path).unwrap());
+        }
+        assert_eq!(
+            backups,
+            [
+                backup("settings.yaml.bak"),
+                backup("settings.yaml.bak.2"),
+                backup("settings.yaml.bak.3"),
+            ]
+        );
+        for (index, backup) in backups.iter().enumerate() {
+            let contents = fs::read_to_string(backup).unwrap();
+            assert_eq!(contents, format!("version: {}", index + 1));
+        }
+
+        // Gaps are left alone, so the newest backup keeps the highest number
+        fs::remove_file(&backups[0]).unwrap();
+        fs::remove_file(&backups[1]).unwrap();
+        assert_eq!(back_up(&path).unwrap(), backup("settings.yaml.bak.4"));
+        fs::write(backup("settings.yaml.bak.tmp"), "").unwrap();
+        fs::write(backup("other.yaml.bak.9"), "").unwrap();
+        assert_eq!(back_up(&path).unwrap(), backup("settings.yaml.bak.5"));
+        assert!(back_up(&dir.path().join("missing.yaml")).is_err());
+    }
+}
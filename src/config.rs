@@ -1,23 +1,442 @@
+use crate::format::ConfigFormat;
 use crate::guard_clause;
-use config::{Config, ConfigError, File};
-use dirs_next::home_dir;
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::keys::{parse_combo_range, KeyCombo, KeyError};
+use crate::migration::{migrate, MigrationError, CURRENT_VERSION};
+use crate::validation::{validate, Severity, ValidationIssue};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, ConfigError, File, FileFormat};
+use dirs_next::{config_dir, home_dir};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 
-#[derive(Eq, PartialEq, Hash, Debug, Deserialize)]
+const CONFIG_FILE_STEM: &str = "desktop-indicator";
+const CONFIG_DIR_NAME: &str = "desktop-indicator";
+const CONFIG_PATH_ENV: &str = "DESKTOP_INDICATOR_CONFIG";
+const BASE_CONFIG_PATH_ENV: &str = "DESKTOP_INDICATOR_BASE_CONFIG";
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct HotKey {
+    /// The first key press as a single string like `Alt+Shift+1`, instead of `modifier_keys` and
+    /// `trigger_key`. A digit range like `Alt+1-9` binds every digit to the desktop of that number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "crate::schema::keys_schema")]
+    pub keys: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(schema_with = "crate::schema::key_names_schema")]
+    pub modifier_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[schemars(schema_with = "crate::schema::key_name_schema")]
+    pub trigger_key: String,
+    /// Further key presses that must follow within `sequence_timeout_ms`, e.g. `3` after `Alt+D`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub then: Vec<KeyChord>,
+    pub action: Action,
+}
+
+/// A single key press within a hotkey sequence.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct KeyChord {
+    #[serde(default)]
+    #[schemars(schema_with = "crate::schema::key_names_schema")]
     pub modifier_keys: Vec<String>,
+    #[schemars(schema_with = "crate::schema::key_name_schema")]
     pub trigger_key: String,
-    pub target_desktop_index: u32,
 }
 
-#[derive(Debug, Deserialize)]
+impl KeyChord {
+    pub fn combo(&self) -> Result<KeyCombo, KeyError> {
+        KeyCombo::from_names(&self.trigger_key, &self.modifier_keys)
+    }
+}
+
+/// One key sequence of a hotkey and what it does, with a digit range in `keys` expanded.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Binding {
+    pub keys: Vec<KeyCombo>,
+    pub action: Action,
+}
+
+impl HotKey {
+    /// Combinations of the first key press, from `keys` if set. Those from a digit range come with
+    /// the zero-based index of the desktop their digit stands for.
+    pub fn first_combos(&self) -> Result<Vec<(KeyCombo, Option<u32>)>, KeyError> {
+        let Some(keys) = &self.keys else {
+            let combo = KeyCombo::from_names(&self.trigger_key, &self.modifier_keys)?;
+            return Ok(vec![(combo, None)]);
+        };
+        let combos = parse_combo_range(keys)?;
+        Ok(combos
+            .into_iter()
+            .map(|(combo, digit)| (combo, digit.map(|digit| digit - 1)))
+            .collect())
+    }
+
+    pub fn bindings(&self) -> Result<Vec<Binding>, KeyError> {
+        let then: Result<Vec<_>, _> = self.then.iter().map(KeyChord::combo).collect();
+        let then = then?;
+
+        let bindings = self.first_combos()?.into_iter().map(|(first, index)| {
+            let mut action = self.action.clone();
+            if let (Some(index), Some(target)) = (index, action.target_mut()) {
+                *target = DesktopTarget::Index(index);
+            }
+            let keys = std::iter::once(first).chain(then.iter().cloned()).collect();
+            Binding { keys, action }
+        });
+        Ok(bindings.collect())
+    }
+}
+
+/// What a hotkey does when pressed, tagged by `type`.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Action {
+    Switch {
+        #[serde(default)]
+        target: DesktopTarget,
+    },
+    /// Follows the window to the target desktop if `follow` is set, or else if `follow_moved_windows` is.
+    MoveWindow {
+        #[serde(default)]
+        target: DesktopTarget,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        follow: Option<bool>,
+    },
+    /// Always follows the window to the target desktop.
+    MoveAndFollow {
+        #[serde(default)]
+        target: DesktopTarget,
+    },
+    Rename {
+        #[serde(default)]
+        target: DesktopTarget,
+        name: String,
+    },
+    /// Adds a desktop after the last one.
+    Create {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// Switches to the new desktop once it exists.
+        #[serde(default)]
+        switch: bool,
+    },
+    /// Removes the target desktop, moving its windows to `fallback`.
+    Remove {
+        #[serde(default)]
+        target: DesktopTarget,
+        #[serde(default = "default_remove_fallback")]
+        fallback: DesktopTarget,
+    },
+    /// Starts `command` with `args`, without waiting for it to finish.
+    RunCommand {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    OpenTaskView,
+    /// Reloads the settings files, e.g. after changing an icon file they reference.
+    ReloadConfig,
+}
+
+impl Action {
+    pub fn target(&self) -> Option<&DesktopTarget> {
+        match self {
+            Action::Switch { target }
+            | Action::MoveWindow { target, .. }
+            | Action::MoveAndFollow { target }
+            | Action::Rename { target, .. }
+            | Action::Remove { target, .. } => Some(target),
+            Action::Create { .. }
+            | Action::RunCommand { .. }
+            | Action::OpenTaskView
+            | Action::ReloadConfig => None,
+        }
+    }
+
+    pub fn target_mut(&mut self) -> Option<&mut DesktopTarget> {
+        match self {
+            Action::Switch { target }
+            | Action::MoveWindow { target, .. }
+            | Action::MoveAndFollow { target }
+            | Action::Rename { target, .. }
+            | Action::Remove { target, .. } => Some(target),
+            Action::Create { .. }
+            | Action::RunCommand { .. }
+            | Action::OpenTaskView
+            | Action::ReloadConfig => None,
+        }
+    }
+}
+
+fn default_remove_fallback() -> DesktopTarget {
+    DesktopTarget::Previous { wrap: true }
+}
+
+/// Which desktop a hotkey acts on, looked up every time the hotkey is pressed.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DesktopTarget {
+    /// The desktop that is active when the hotkey is pressed.
+    #[default]
+    Current,
+    /// Zero-based position, which changes when desktops are reordered.
+    Index(u32),
+    /// Desktop name as shown in Task View.
+    Name(String),
+    /// Desktop GUID, with or without braces. Only available on Windows.
+    Guid(String),
+    /// The desktop after the current one, going back to the first after the last with `wrap`.
+    Next {
+        #[serde(default)]
+        wrap: bool,
+    },
+    /// The desktop before the current one, going to the last from the first with `wrap`.
+    Previous {
+        #[serde(default)]
+        wrap: bool,
+    },
+    First,
+    Last,
+    /// The desktop that was active before the current one, so pressing the hotkey again returns.
+    LastUsed,
+    /// One step back in the desktop history, like a browser's back button.
+    Back,
+    /// One step forward again after going back.
+    Forward,
+}
+
+impl DesktopTarget {
+    /// Whether the target depends on the current desktop rather than naming a specific one.
+    pub fn is_relative(&self) -> bool {
+        matches!(
+            self,
+            DesktopTarget::Next { .. }
+                | DesktopTarget::Previous { .. }
+                | DesktopTarget::LastUsed
+                | DesktopTarget::Back
+                | DesktopTarget::Forward
+        )
+    }
+}
+
+impl Display for DesktopTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DesktopTarget::Current => write!(f, "current desktop"),
+            DesktopTarget::Index(index) => write!(f, "desktop {}", index),
+            DesktopTarget::Name(name) => write!(f, "desktop named '{}'", name),
+            DesktopTarget::Guid(guid) => write!(f, "desktop with GUID {}", guid),
+            DesktopTarget::Next { .. } => write!(f, "next desktop"),
+            DesktopTarget::Previous { .. } => write!(f, "previous desktop"),
+            DesktopTarget::First => write!(f, "first desktop"),
+            DesktopTarget::Last => write!(f, "last desktop"),
+            DesktopTarget::LastUsed => write!(f, "last used desktop"),
+            DesktopTarget::Back => write!(f, "desktop back in history"),
+            DesktopTarget::Forward => write!(f, "desktop forward in history"),
+        }
+    }
+}
+
+/// Annotated YAML equivalent of `Settings::default()`.
+pub const DEFAULT_CONFIG: &str = include_str!("../assets/default-config.yaml");
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct IconSettings {
+    /// Falls back to the built-in icon when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Zero-based desktop index to icon path.
+    pub desktops: BTreeMap<u32, String>,
+    /// Icons picked by desktop GUID or name, which stay with a desktop when it is moved. A GUID
+    /// rule wins over an exact name, which wins over a name pattern, which wins over `desktops`.
+    /// Among rules of the same kind, the first that matches wins.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<IconRule>,
+    /// Draws an icon for every desktop without an icon file, in place of the default icon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated: Option<GeneratedIcons>,
+    /// Name of the entry in `themes` whose variants replace the icons above.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Which variant of the theme to use, following the system by default.
+    pub appearance: Appearance,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub themes: BTreeMap<String, IconTheme>,
+}
+
+/// Icons for a light and a dark taskbar.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct IconTheme {
+    pub light: IconSet,
+    pub dark: IconSet,
+}
+
+/// One variant of a theme. Fields left out fall back to those directly under `icons`.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct IconSet {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Merged with `icons.desktops`, taking precedence for the same index.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub desktops: BTreeMap<u32, String>,
+    /// Checked ahead of `icons.rules`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<IconRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated: Option<GeneratedIcons>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct IconRule {
+    #[serde(flatten)]
+    pub desktop: DesktopPattern,
+    /// Path of the icon file for the matching desktops.
+    pub icon: String,
+}
+
+/// Which desktops an icon rule applies to.
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DesktopPattern {
+    /// Desktop GUID, with or without braces. Only available on Windows.
+    Guid(String),
+    /// The whole desktop name, where `*` stands for any text and `?` for any one character.
+    Name(String),
+    /// Regular expression matching part of the desktop name, unless anchored with `^` and `$`.
+    Regex(String),
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Appearance {
+    /// Follows the light or dark mode of the system.
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl IconSettings {
+    /// The icons to show when the system is in dark mode or not, with those of the selected theme
+    /// variant on top of the plain ones.
+    pub fn active_set(&self, system_dark: bool) -> IconSet {
+        let base = IconSet {
+            default: self.default.clone(),
+            desktops: self.desktops.clone(),
+            rules: self.rules.clone(),
+            generated: self.generated.clone(),
+        };
+        let Some(theme) = self.theme.as_ref().and_then(|name| self.themes.get(name)) else {
+            return base;
+        };
+
+        let dark = match self.appearance {
+            Appearance::Auto => system_dark,
+            Appearance::Light => false,
+            Appearance::Dark => true,
+        };
+        let variant = if dark { &theme.dark } else { &theme.light };
+
+        let mut desktops = base.desktops;
+        desktops.extend(variant.desktops.clone());
+        let mut rules = variant.rules.clone();
+        rules.extend(base.rules);
+        IconSet {
+            default: variant.default.clone().or(base.default),
+            desktops,
+            rules,
+            generated: variant.generated.clone().or(base.generated),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct GeneratedIcons {
+    pub label: IconLabel,
+    /// Height of the label on a 32 px icon, scaled down for smaller sizes.
+    pub font_size: u32,
+    /// Color as `#RGB`, `#RRGGBB` or `#RRGGBBAA`.
+    pub foreground: String,
+    pub background: String,
+    pub shape: IconShape,
+}
+
+impl Default for GeneratedIcons {
+    fn default() -> Self {
+        Self {
+            label: IconLabel::default(),
+            font_size: 20,
+            foreground: "#FFFFFF".to_string(),
+            background: "#2D5DA8".to_string(),
+            shape: IconShape::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IconLabel {
+    /// One-based position of the desktop.
+    #[default]
+    Number,
+    /// First letters of the desktop name, falling back to the number for unnamed desktops.
+    Initials,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IconShape {
+    Square,
+    #[default]
+    Rounded,
+    Circle,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
 pub struct Settings {
-    pub default_icon_path: String,
-    pub desktop_index_to_icon_path: HashMap<u32, String>,
-    pub switch_desktop_hotkeys: Vec<HotKey>,
-    pub move_window_hotkeys: Vec<HotKey>,
+    /// Layout version of the file, older layouts are migrated on load.
+    pub version: u32,
+    pub icons: IconSettings,
+    pub hotkeys: Vec<HotKey>,
     pub follow_moved_windows: bool,
+    /// Number of desktops remembered for the `back` and `forward` targets, at least one.
+    pub history_depth: usize,
+    /// How long to wait for the next key of a hotkey sequence, in milliseconds.
+    pub sequence_timeout_ms: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            icons: IconSettings::default(),
+            hotkeys: vec![],
+            follow_moved_windows: true,
+            history_depth: 10,
+            sequence_timeout_ms: 1500,
+        }
+    }
+}
+
+/// The files settings are merged from, in increasing order of precedence.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ConfigLocation {
+    /// Shared settings, e.g. a team-wide file, that the other files build upon.
+    pub base: Option<PathBuf>,
+    pub primary: PathBuf,
+    /// Per-machine overrides next to the primary file, only merged when present.
+    pub local_override: PathBuf,
 }
 
 #[derive(Debug)]
@@ -25,25 +444,461 @@ pub struct Settings {
 pub enum SettingsError {
     ConfigError(ConfigError),
     NoHomeDirError,
+    InvalidSettings(Vec<ValidationIssue>),
+    UnsupportedLayout(PathBuf, MigrationError),
 }
 
-impl Settings {
-    pub fn new() -> Result<Self, SettingsError> {
-        let Some(home_dir) = home_dir() else {
-            return Err(SettingsError::NoHomeDirError);
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::ConfigError(error) => write!(f, "{}", error),
+            SettingsError::NoHomeDirError => write!(f, "could not determine the home directory"),
+            SettingsError::UnsupportedLayout(path, error) => {
+                write!(f, "could not upgrade {}: {}", path.display(), error)
+            }
+            SettingsError::InvalidSettings(issues) => {
+                write!(f, "found {} problem(s) in the settings", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ConfigLocation {
+    /// Resolves explicit paths first, then environment overrides, then the default search locations.
+    pub fn resolve(
+        config_path: Option<PathBuf>,
+        base_config_path: Option<PathBuf>,
+    ) -> Result<Self, SettingsError> {
+        Self::resolve_from(
+            config_path,
+            base_config_path,
+            |name| env::var_os(name),
+            &default_search_dirs(),
+        )
+    }
+
+    /// Like `resolve`, with the environment and the directories to search passed in.
+    fn resolve_from(
+        config_path: Option<PathBuf>,
+        base_config_path: Option<PathBuf>,
+        env_var: impl Fn(&str) -> Option<OsString>,
+        search_dirs: &[PathBuf],
+    ) -> Result<Self, SettingsError> {
+        let primary = match config_path.or_else(|| env_var(CONFIG_PATH_ENV).map(PathBuf::from)) {
+            Some(path) => path,
+            None => search_config_file(search_dirs)?,
         };
-        let settings = guard_clause!(
-            Config::builder()
-                .add_source(File::from(home_dir.join("desktop-indicator.yaml")))
-                .build(),
-            error,
-            {
-                return Err(SettingsError::ConfigError(error));
+        let base = base_config_path.or_else(|| env_var(BASE_CONFIG_PATH_ENV).map(PathBuf::from));
+        let local_override = local_override_path(&primary);
+
+        Ok(Self {
+            base,
+            primary,
+            local_override,
+        })
+    }
+
+    pub fn paths(&self) -> Vec<&Path> {
+        self.base
+            .iter()
+            .map(|it| it.as_path())
+            .chain([self.primary.as_path(), self.local_override.as_path()])
+            .collect()
+    }
+}
+
+/// The directory of the executable for portable installs, then the platform config directory (XDG
+/// on Linux, AppData on Windows), then the home directory.
+fn default_search_dirs() -> Vec<PathBuf> {
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    [
+        exe_dir,
+        config_dir().map(|dir| dir.join(CONFIG_DIR_NAME)),
+        home_dir(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Tries every supported format in each of `search_dirs` in turn.
+fn search_config_file(search_dirs: &[PathBuf]) -> Result<PathBuf, SettingsError> {
+    for search_dir in search_dirs {
+        for format in ConfigFormat::ALL {
+            let path = search_dir.join(format!("{}.{}", CONFIG_FILE_STEM, format.extension()));
+            if path.is_file() {
+                return Ok(path);
             }
-        );
+        }
+    }
+    // Nothing exists yet, so point at the home directory file that older versions always used
+    match search_dirs.last() {
+        Some(dir) => Ok(dir.join(format!(
+            "{}.{}",
+            CONFIG_FILE_STEM,
+            ConfigFormat::Yaml.extension()
+        ))),
+        None => Err(SettingsError::NoHomeDirError),
+    }
+}
+
+/// `desktop-indicator.yaml` -> `desktop-indicator.local.yaml`
+fn local_override_path(primary: &Path) -> PathBuf {
+    let stem = primary
+        .file_stem()
+        .map(|it| it.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match primary.extension() {
+        Some(extension) => format!("{}.local.{}", stem, extension.to_string_lossy()),
+        None => format!("{}.local", stem),
+    };
+    primary.with_file_name(file_name)
+}
+
+impl Settings {
+    /// Loads the layered settings, logging problems that don't keep the app from starting.
+    pub fn load(location: &ConfigLocation) -> Result<Self, SettingsError> {
+        let (settings, warnings) = Self::load_with_warnings(location)?;
+        for warning in warnings {
+            log::warn!("{}", warning);
+        }
+        Ok(settings)
+    }
+
+    /// Like `load`, but returns the warnings instead of logging them.
+    pub fn load_with_warnings(
+        location: &ConfigLocation,
+    ) -> Result<(Self, Vec<ValidationIssue>), SettingsError> {
+        let mut builder = Config::builder();
+        if let Some(base) = &location.base {
+            log::info!("Merging base settings from {}", base.display());
+            builder = add_migrated_source(builder, base)?;
+        }
+        log::info!("Merging settings from {}", location.primary.display());
+        builder = add_migrated_source(builder, &location.primary)?;
+        if location.local_override.is_file() {
+            log::info!(
+                "Merging local overrides from {}",
+                location.local_override.display()
+            );
+            builder = add_migrated_source(builder, &location.local_override)?;
+        }
+
+        let settings = Self::deserialize(builder)?;
+        let issues = validate(&settings);
+        if issues.iter().any(|issue| issue.severity == Severity::Error) {
+            return Err(SettingsError::InvalidSettings(issues));
+        }
+        Ok((settings, issues))
+    }
+
+    /// Reads a single file, upgraded to the current layout, without layering or validation.
+    pub fn read_file(path: &Path) -> Result<Self, SettingsError> {
+        Self::deserialize(add_migrated_source(Config::builder(), path)?)
+    }
+
+    fn deserialize(builder: ConfigBuilder<DefaultState>) -> Result<Self, SettingsError> {
+        let settings = guard_clause!(builder.build(), error, {
+            return Err(SettingsError::ConfigError(error));
+        });
         match settings.try_deserialize() {
             Ok(result) => Ok(result),
             Err(error) => Err(SettingsError::ConfigError(error)),
         }
     }
 }
+
+/// Reads a single file and upgrades it to the current layout, returning the version it was at if it changed.
+pub fn read_migrated(path: &Path) -> Result<(Value, Option<u32>), SettingsError> {
+    let config = guard_clause!(
+        Config::builder().add_source(File::from(path)).build(),
+        error,
+        {
+            return Err(SettingsError::ConfigError(error));
+        }
+    );
+    let mut settings: Value = guard_clause!(config.try_deserialize(), error, {
+        return Err(SettingsError::ConfigError(error));
+    });
+    let original_version = guard_clause!(migrate(&mut settings), error, {
+        return Err(SettingsError::UnsupportedLayout(path.to_path_buf(), error));
+    });
+    Ok((settings, original_version))
+}
+
+// Files are migrated one by one, as layered files don't necessarily share a version
+fn add_migrated_source(
+    builder: ConfigBuilder<DefaultState>,
+    path: &Path,
+) -> Result<ConfigBuilder<DefaultState>, SettingsError> {
+    let (settings, original_version) = read_migrated(path)?;
+    if let Some(original_version) = original_version {
+        log::warn!(
+            "{} uses settings version {}, run `DesktopIndicator migrate --write` to upgrade it",
+            path.display(),
+            original_version
+        );
+    }
+    Ok(builder.add_source(File::from_str(&settings.to_string(), FileFormat::Json)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn icon_settings(yaml: &str) -> IconSettings {
+        Config::builder()
+            .add_source(File::from_str(yaml, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    const THEMED: &str = r##"
+default: plain.ico
+desktops: { 0: plain-1.ico, 1: plain-2.ico }
+rules: [{ name: Work, icon: plain-work.ico }]
+generated: { font_size: 12 }
+theme: mono
+themes:
+  mono:
+    light:
+      default: light.ico
+      desktops: { 1: light-2.ico }
+      rules: [{ name: Mail, icon: light-mail.ico }]
+    dark:
+      desktops: { 2: dark-3.ico }
+      generated: { foreground: "#000000" }
+"##;
+
+    #[test]
+    fn picks_the_variant_for_the_appearance() {
+        let cases = [
+            ("auto", false, "light.ico"),
+            ("auto", true, "plain.ico"),
+            ("light", true, "light.ico"),
+            ("dark", false, "plain.ico"),
+        ];
+        for (appearance, system_dark, default) in cases {
+            let yaml = format!("{}appearance: {}", THEMED, appearance);
+            let active = icon_settings(&yaml).active_set(system_dark);
+            assert_eq!(
+                active.default.as_deref(),
+                Some(default),
+                "{} with a dark system: {}",
+                appearance,
+                system_dark
+            );
+        }
+    }
+
+    #[test]
+    fn layers_the_variant_over_the_plain_icons() {
+        let settings = icon_settings(THEMED);
+        let icon_paths = |icons: &BTreeMap<u32, String>| -> Vec<(u32, String)> {
+            icons
+                .iter()
+                .map(|(index, path)| (*index, path.clone()))
+                .collect()
+        };
+        let rule_icons = |icons: &IconSet| -> Vec<String> {
+            icons.rules.iter().map(|rule| rule.icon.clone()).collect()
+        };
+
+        let light = settings.active_set(false);
+        assert_eq!(
+            icon_paths(&light.desktops),
+            [
+                (0, "plain-1.ico".to_string()),
+                (1, "light-2.ico".to_string())
+            ]
+        );
+        assert_eq!(rule_icons(&light), ["light-mail.ico", "plain-work.ico"]);
+        assert_eq!(light.generated.map(|it| it.font_size), Some(12));
+
+        let dark = settings.active_set(true);
+        assert_eq!(dark.desktops.len(), 3);
+        assert_eq!(rule_icons(&dark), ["plain-work.ico"]);
+        // Generated icons are replaced as a whole rather than merged field by field
+        let generated = dark.generated.unwrap();
+        assert_eq!(generated.foreground, "#000000");
+        assert_eq!(generated.font_size, GeneratedIcons::default().font_size);
+    }
+
+    #[test]
+    fn uses_the_plain_icons_without_a_theme() {
+        for yaml in [
+            THEMED.replace("theme: mono", ""),
+            THEMED.replace("theme: mono", "theme: missing"),
+        ] {
+            let settings = icon_settings(&yaml);
+            for system_dark in [false, true] {
+                let active = settings.active_set(system_dark);
+                assert_eq!(active.default.as_deref(), Some("plain.ico"));
+                assert_eq!(active.desktops, settings.desktops);
+                assert_eq!(active.rules, settings.rules);
+                assert_eq!(active.generated, settings.generated);
+            }
+        }
+    }
+
+    /// A config directory, a home directory and a directory with the executable, all empty.
+    struct SearchDirs {
+        root: tempfile::TempDir,
+    }
+
+    impl SearchDirs {
+        fn new() -> Self {
+            let root = tempfile::tempdir().unwrap();
+            for dir in ["exe", "config", "home"] {
+                fs::create_dir(root.path().join(dir)).unwrap();
+            }
+            Self { root }
+        }
+
+        fn path(&self, path: &str) -> PathBuf {
+            self.root.path().join(path)
+        }
+
+        fn create(&self, path: &str, contents: &str) -> PathBuf {
+            let path = self.path(path);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+
+        fn resolve(
+            &self,
+            config_path: Option<&str>,
+            env: &[(&str, &str)],
+        ) -> Result<ConfigLocation, SettingsError> {
+            let env: HashMap<String, OsString> = env
+                .iter()
+                .map(|(name, path)| (name.to_string(), self.path(path).into()))
+                .collect();
+            ConfigLocation::resolve_from(
+                config_path.map(|path| self.path(path)),
+                None,
+                |name| env.get(name).cloned(),
+                &[self.path("exe"), self.path("config"), self.path("home")],
+            )
+        }
+
+        fn primary(&self, config_path: Option<&str>, env: &[(&str, &str)]) -> PathBuf {
+            self.resolve(config_path, env).unwrap().primary
+        }
+    }
+
+    #[test]
+    fn searches_for_the_settings_file_in_order() {
+        let dirs = SearchDirs::new();
+        let env = [(CONFIG_PATH_ENV, "from-env.yaml")];
+
+        // Nothing exists yet, so the file to create goes into the home directory
+        assert_eq!(
+            dirs.primary(None, &[]),
+            dirs.path("home/desktop-indicator.yaml")
+        );
+        dirs.create("home/desktop-indicator.json", "{}");
+        assert_eq!(
+            dirs.primary(None, &[]),
+            dirs.path("home/desktop-indicator.json")
+        );
+        dirs.create("config/desktop-indicator.toml", "");
+        assert_eq!(
+            dirs.primary(None, &[]),
+            dirs.path("config/desktop-indicator.toml")
+        );
+        dirs.create("config/desktop-indicator.yaml", "");
+        assert_eq!(
+            dirs.primary(None, &[]),
+            dirs.path("config/desktop-indicator.yaml")
+        );
+        dirs.create("exe/desktop-indicator.json", "{}");
+        assert_eq!(
+            dirs.primary(None, &[]),
+            dirs.path("exe/desktop-indicator.json")
+        );
+
+        // Explicit paths are taken whether they exist or not
+        assert_eq!(dirs.primary(None, &env), dirs.path("from-env.yaml"));
+        let from_flag = dirs.primary(Some("from-flag.toml"), &env);
+        assert_eq!(from_flag, dirs.path("from-flag.toml"));
+    }
+
+    #[test]
+    fn needs_somewhere_to_look() {
+        let location = ConfigLocation::resolve_from(None, None, |_| None, &[]);
+        assert!(matches!(location, Err(SettingsError::NoHomeDirError)));
+    }
+
+    #[test]
+    fn puts_the_local_override_next_to_the_settings_file() {
+        let cases = [
+            ("desktop-indicator.yaml", "desktop-indicator.local.yaml"),
+            ("dir/settings.v2.toml", "dir/settings.v2.local.toml"),
+            ("settings", "settings.local"),
+        ];
+        for (primary, expected) in cases {
+            assert_eq!(
+                local_override_path(Path::new(primary)),
+                PathBuf::from(expected),
+                "{}",
+                primary
+            );
+        }
+    }
+
+    #[test]
+    fn layers_the_base_settings_and_local_overrides() {
+        let dirs = SearchDirs::new();
+        dirs.create("base.yaml", "history_depth: 3\nsequence_timeout_ms: 500");
+        let primary = dirs.create("config/desktop-indicator.yaml", "history_depth: 5");
+        let env = [(BASE_CONFIG_PATH_ENV, "base.yaml")];
+
+        let location = dirs.resolve(None, &env).unwrap();
+        assert_eq!(
+            location,
+            ConfigLocation {
+                base: Some(dirs.path("base.yaml")),
+                primary: primary.clone(),
+                local_override: dirs.path("config/desktop-indicator.local.yaml"),
+            }
+        );
+        let settings = Settings::load(&location).unwrap();
+        assert_eq!(
+            (settings.history_depth, settings.sequence_timeout_ms),
+            (5, 500)
+        );
+
+        dirs.create(
+            "config/desktop-indicator.local.yaml",
+            "sequence_timeout_ms: 800\nfollow_moved_windows: true",
+        );
+        let settings = Settings::load(&location).unwrap();
+        assert_eq!(
+            (
+                settings.history_depth,
+                settings.sequence_timeout_ms,
+                settings.follow_moved_windows
+            ),
+            (5, 800, true)
+        );
+        assert_eq!(
+            location.paths(),
+            [
+                dirs.path("base.yaml"),
+                primary,
+                dirs.path("config/desktop-indicator.local.yaml")
+            ]
+        );
+    }
+}
@@ -1,115 +1,101 @@
+use crate::backend::{DesktopEvent, VirtualDesktopBackend};
 use crate::guard_clause;
 use bus::Bus;
 use std::sync::{Arc, Mutex};
 use std::{sync, thread};
-use winvd::{
-    get_current_desktop, get_desktops, listen_desktop_events, Desktop, DesktopEvent, DesktopEventThread,
-    Error,
-};
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct DesktopInfo {
     pub name: String,
     pub index: u32,
+    /// Stable identifier where the platform has one, i.e. the desktop GUID on Windows.
+    pub guid: Option<String>,
 }
 
-impl From<Desktop> for DesktopInfo {
-    fn from(desktop: Desktop) -> Self {
-        Self {
-            name: desktop.get_name().unwrap(),
-            index: desktop.get_index().unwrap(),
-        }
-    }
+/// GUIDs are compared without braces and case, as they are written in several styles
+pub fn normalize_guid(guid: &str) -> String {
+    guid.trim_start_matches('{')
+        .trim_end_matches('}')
+        .to_ascii_uppercase()
 }
 
 const BUS_BUFFER_SIZE: usize = 32;
 
-#[derive(Clone)]
-pub struct DesktopEventHooks {
-    _listener_thread: Arc<DesktopEventThread>,
-    on_active_change_hook: Arc<Mutex<Bus<Option<DesktopInfo>>>>,
-    on_desktops_change_hook: Arc<Mutex<Bus<Option<Vec<DesktopInfo>>>>>,
+type ActiveChangeHook = Arc<Mutex<Bus<Option<DesktopInfo>>>>;
+type DesktopsChangeHook = Arc<Mutex<Bus<Option<Vec<DesktopInfo>>>>>;
+
+pub struct DesktopEventHooks<B: VirtualDesktopBackend> {
+    backend: B,
+    _listener: Arc<B::EventListener>,
+    on_active_change_hook: ActiveChangeHook,
+    on_desktops_change_hook: DesktopsChangeHook,
+}
+
+// Not derived, as that would also require the listener to be `Clone`, though it's shared
+impl<B: VirtualDesktopBackend + Clone> Clone for DesktopEventHooks<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            _listener: self._listener.clone(),
+            on_active_change_hook: self.on_active_change_hook.clone(),
+            on_desktops_change_hook: self.on_desktops_change_hook.clone(),
+        }
+    }
 }
 
 #[allow(unused)]
-impl DesktopEventHooks {
-    pub fn new() -> Result<Self, Error> {
+impl<B: VirtualDesktopBackend> DesktopEventHooks<B> {
+    pub fn new(backend: B) -> Result<Self, B::Error> {
         let (tx, rx) = sync::mpsc::channel::<DesktopEvent>();
-        let listener_thread = listen_desktop_events(tx)?;
+        let listener = backend.listen_desktop_events(tx)?;
 
         let on_active_change_hook = Arc::new(Mutex::new(Bus::new(BUS_BUFFER_SIZE)));
         let on_desktops_change_hook = Arc::new(Mutex::new(Bus::new(BUS_BUFFER_SIZE)));
 
         let _thread = {
+            let backend = backend.clone();
             let on_active_change_hook = on_active_change_hook.clone();
             let on_desktops_change_hook = on_desktops_change_hook.clone();
 
             thread::spawn(move || {
                 for event in rx {
-                    log::info!("Event received: {:?}", event);
-                    let current_desktop = guard_clause!(get_current_desktop(), error, {
-                        log::error!("Could not get current desktop: {:?}", error);
-                        continue;
-                    });
-
-                    if match event {
-                        DesktopEvent::DesktopCreated(_) => true,
-                        DesktopEvent::DesktopDestroyed { .. } => true,
-                        DesktopEvent::DesktopChanged { .. } => true,
-                        DesktopEvent::DesktopNameChanged(desktop, _) => desktop == current_desktop,
-                        DesktopEvent::DesktopMoved { .. } => true,
-                        _ => false,
-                    } {
-                        let Ok(mut locked_hook) = on_active_change_hook.try_lock() else {
-                            log::error!("Could not lock the active desktop change hook");
-                            continue;
-                        };
-                        locked_hook.broadcast(Some(current_desktop.into()));
-                    }
-
-                    if match event {
-                        DesktopEvent::DesktopCreated(_) => true,
-                        DesktopEvent::DesktopDestroyed { .. } => true,
-                        DesktopEvent::DesktopNameChanged(_, _) => true,
-                        DesktopEvent::DesktopMoved { .. } => true,
-                        _ => false,
-                    } {
-                        let Ok(mut locked_hook) = on_desktops_change_hook.try_lock() else {
-                            log::error!("Could not lock the desktop change hook");
-                            continue;
-                        };
-                        let desktops = guard_clause!(get_desktops(), error, {
-                            log::error!("Could not get desktops: {:?}", error);
-                            continue;
-                        });
-                        locked_hook
-                            .broadcast(Some(desktops.into_iter().map(|it| it.into()).collect()));
-                    }
+                    dispatch_event(
+                        &backend,
+                        event,
+                        &on_active_change_hook,
+                        &on_desktops_change_hook,
+                    );
                 }
             })
         };
 
         Ok(Self {
-            _listener_thread: Arc::new(listener_thread),
+            backend,
+            _listener: Arc::new(listener),
             on_active_change_hook,
             on_desktops_change_hook,
         })
     }
 
-    pub fn on_active_desktop_change(&self, event_handler: impl Fn(DesktopInfo)) {
-        let current_desktop = guard_clause!(get_current_desktop(), error, {
-            log::error!("Could not get current desktop: {:?}", error);
-            return;
-        });
-        event_handler(current_desktop.into());
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
 
-        let mut change_hook = guard_clause!(self.on_active_change_hook.try_lock(), error, {
+    pub fn on_active_desktop_change(&self, event_handler: impl Fn(DesktopInfo)) {
+        // Subscribed first, so that no change is missed after reading the current desktop
+        let mut change_hook = guard_clause!(self.on_active_change_hook.lock(), error, {
             log::error!("Could not lock the active desktop change hook: {:?}", error);
             return;
         });
         let rx = change_hook.add_rx();
         drop(change_hook);
 
+        let current_desktop = guard_clause!(self.backend.get_current_desktop(), error, {
+            log::error!("Could not get current desktop: {:?}", error);
+            return;
+        });
+        event_handler(current_desktop);
+
         for event in rx {
             if let Some(event) = event {
                 event_handler(event);
@@ -120,19 +106,19 @@ impl DesktopEventHooks {
     }
 
     pub fn on_desktops_change(&self, event_handler: impl Fn(Vec<DesktopInfo>)) {
-        let desktops = guard_clause!(get_desktops(), error, {
-            log::error!("Could not get desktops: {:?}", error);
-            return;
-        });
-        event_handler(desktops.into_iter().map(|it| it.into()).collect());
-
-        let mut change_hook = guard_clause!(self.on_desktops_change_hook.try_lock(), error, {
+        let mut change_hook = guard_clause!(self.on_desktops_change_hook.lock(), error, {
             log::error!("Could not lock the desktops change hook: {:?}", error);
             return;
         });
         let rx = change_hook.add_rx();
         drop(change_hook);
 
+        let desktops = guard_clause!(self.backend.get_desktops(), error, {
+            log::error!("Could not get desktops: {:?}", error);
+            return;
+        });
+        event_handler(desktops);
+
         for event in rx {
             if let Some(event) = event {
                 event_handler(event);
@@ -151,3 +137,147 @@ impl DesktopEventHooks {
         }
     }
 }
+
+fn dispatch_event<B: VirtualDesktopBackend>(
+    backend: &B,
+    event: DesktopEvent,
+    on_active_change_hook: &ActiveChangeHook,
+    on_desktops_change_hook: &DesktopsChangeHook,
+) {
+    let current_desktop = guard_clause!(backend.get_current_desktop(), error, {
+        log::error!("Could not get current desktop: {:?}", error);
+        return;
+    });
+
+    if match event {
+        DesktopEvent::DesktopCreated => true,
+        DesktopEvent::DesktopDestroyed => true,
+        DesktopEvent::DesktopChanged => true,
+        DesktopEvent::DesktopNameChanged { index } => index == current_desktop.index,
+        DesktopEvent::DesktopMoved => true,
+        DesktopEvent::Other => false,
+    } {
+        let Ok(mut locked_hook) = on_active_change_hook.lock() else {
+            log::error!("Could not lock the active desktop change hook");
+            return;
+        };
+        locked_hook.broadcast(Some(current_desktop));
+    }
+
+    if matches!(
+        event,
+        DesktopEvent::DesktopCreated
+            | DesktopEvent::DesktopDestroyed
+            | DesktopEvent::DesktopNameChanged { .. }
+            | DesktopEvent::DesktopMoved
+    ) {
+        let Ok(mut locked_hook) = on_desktops_change_hook.lock() else {
+            log::error!("Could not lock the desktop change hook");
+            return;
+        };
+        let desktops = guard_clause!(backend.get_desktops(), error, {
+            log::error!("Could not get desktops: {:?}", error);
+            return;
+        });
+        locked_hook.broadcast(Some(desktops));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeBackend;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Subscribes to active desktop changes on a thread of its own, like the tray app does.
+    fn subscribe_active(hooks: &DesktopEventHooks<FakeBackend>) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        let hooks = hooks.clone();
+        thread::spawn(move || {
+            hooks.on_active_desktop_change(|info| tx.send(info.name).unwrap());
+        });
+        rx
+    }
+
+    fn subscribe_desktops(hooks: &DesktopEventHooks<FakeBackend>) -> Receiver<Vec<String>> {
+        let (tx, rx) = mpsc::channel();
+        let hooks = hooks.clone();
+        thread::spawn(move || {
+            hooks.on_desktops_change(|desktops| {
+                tx.send(desktops.into_iter().map(|info| info.name).collect())
+                    .unwrap()
+            });
+        });
+        rx
+    }
+
+    #[test]
+    fn fans_out_active_desktop_changes() {
+        let backend = FakeBackend::new(&["A", "B", "C"]);
+        let hooks = DesktopEventHooks::new(backend.clone()).unwrap();
+        let subscribers = [subscribe_active(&hooks), subscribe_active(&hooks)];
+        for subscriber in &subscribers {
+            assert_eq!(subscriber.recv_timeout(TIMEOUT).unwrap(), "A");
+        }
+
+        // Events only say what changed, the hooks read the desktops when they get to them
+        let steps: [(&dyn Fn(), &str); 3] = [
+            (&|| backend.switch_desktop(2).unwrap(), "C"),
+            (&|| backend.rename_desktop(2, "Work").unwrap(), "Work"),
+            // Renaming another desktop leaves the current one as it is
+            (
+                &|| {
+                    backend.rename_desktop(0, "Home").unwrap();
+                    backend.rename_desktop(2, "Play").unwrap();
+                },
+                "Play",
+            ),
+        ];
+        for (change, expected) in steps {
+            change();
+            for subscriber in &subscribers {
+                assert_eq!(subscriber.recv_timeout(TIMEOUT).unwrap(), expected);
+            }
+        }
+
+        hooks.terminate();
+        for subscriber in &subscribers {
+            assert!(subscriber.recv_timeout(TIMEOUT).is_err());
+        }
+    }
+
+    #[test]
+    fn fans_out_desktop_list_changes() {
+        let backend = FakeBackend::new(&["A", "B"]);
+        let hooks = DesktopEventHooks::new(backend.clone()).unwrap();
+        let subscribers = [subscribe_desktops(&hooks), subscribe_desktops(&hooks)];
+        for subscriber in &subscribers {
+            assert_eq!(subscriber.recv_timeout(TIMEOUT).unwrap(), ["A", "B"]);
+        }
+
+        // Switching doesn't change the list, so only the later changes arrive
+        backend.switch_desktop(1).unwrap();
+        backend.create_desktop().unwrap();
+        for subscriber in &subscribers {
+            assert_eq!(
+                subscriber.recv_timeout(TIMEOUT).unwrap(),
+                ["A", "B", "Desktop 3"]
+            );
+        }
+        backend.remove_desktop(0, 1).unwrap();
+        for subscriber in &subscribers {
+            assert_eq!(
+                subscriber.recv_timeout(TIMEOUT).unwrap(),
+                ["B", "Desktop 3"]
+            );
+        }
+
+        hooks.terminate();
+        for subscriber in &subscribers {
+            assert!(subscriber.recv_timeout(TIMEOUT).is_err());
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::path::Path;
+
+/// Settings file formats, detected from the file extension.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+#[derive(Debug)]
+#[allow(unused)]
+pub enum FormatError {
+    Yaml(serde_yaml::Error),
+    Toml(toml::ser::Error),
+    Json(serde_json::Error),
+}
+
+impl ConfigFormat {
+    pub const ALL: [ConfigFormat; 3] = [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(&path.extension()?.to_string_lossy())
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConfigFormat::Yaml => "yaml",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+
+    pub fn serialize(&self, settings: &impl Serialize) -> Result<String, FormatError> {
+        match self {
+            // serde_yaml writes enums as `!tag` values, which the config loader can't read back
+            ConfigFormat::Yaml => serde_json::to_value(settings)
+                .map_err(FormatError::Json)
+                .and_then(|value| serde_yaml::to_string(&value).map_err(FormatError::Yaml)),
+            ConfigFormat::Toml => toml::to_string_pretty(settings).map_err(FormatError::Toml),
+            ConfigFormat::Json => serde_json::to_string_pretty(settings).map_err(FormatError::Json),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SETTINGS: &str = r##"
+icons:
+  default: "C:/icons/default.ico"
+  desktops:
+    0: "C:/icons/desktop1.ico"
+    11: "C:/icons/desktop12.ico"
+  theme: outline
+  themes:
+    outline:
+      dark:
+        desktops:
+          2: "C:/icons/desktop3-dark.ico"
+hotkeys:
+  - keys: "Win+1-9"
+    action: { type: switch }
+  - modifier_keys: ["Alt"]
+    trigger_key: "D"
+    then: [{ trigger_key: "3" }]
+    action: { type: move-window, target: { name: "Work" }, follow: false }
+follow_moved_windows: false
+"##;
+
+    /// Writes `contents` to a file of its own and reads it back the way the app loads settings.
+    fn read_back(format: ConfigFormat, contents: &str) -> Settings {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("settings.{}", format.extension()));
+        fs::write(&path, contents).unwrap();
+        Settings::read_file(&path).unwrap()
+    }
+
+    #[test]
+    fn yaml_round_trips_through_toml() {
+        let original = read_back(ConfigFormat::Yaml, SETTINGS);
+        assert_eq!(original.icons.desktops.len(), 2);
+
+        let toml = ConfigFormat::Toml.serialize(&original).unwrap();
+        let from_toml = read_back(ConfigFormat::Toml, &toml);
+        assert_eq!(from_toml, original, "{}", toml);
+
+        let yaml = ConfigFormat::Yaml.serialize(&from_toml).unwrap();
+        let from_yaml = read_back(ConfigFormat::Yaml, &yaml);
+        assert_eq!(from_yaml, original, "{}", yaml);
+        assert_eq!(ConfigFormat::Yaml.serialize(&from_yaml).unwrap(), yaml);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let original = read_back(ConfigFormat::Yaml, SETTINGS);
+        let json = ConfigFormat::Json.serialize(&original).unwrap();
+        assert!(
+            json.contains(r#""11": "C:/icons/desktop12.ico""#),
+            "{}",
+            json
+        );
+        assert_eq!(read_back(ConfigFormat::Json, &json), original);
+    }
+
+    #[test]
+    fn detects_formats_from_extensions() {
+        for format in ConfigFormat::ALL {
+            let path = PathBuf::from(format!("settings.{}", format.extension()));
+            assert_eq!(ConfigFormat::from_path(&path), Some(format));
+        }
+        assert_eq!(ConfigFormat::from_name("YML"), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::from_path(Path::new("settings.ini")), None);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::backend::VirtualDesktopBackend;
+use crate::desktop::{normalize_guid, DesktopEventHooks, DesktopInfo};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Browser-like record of the desktops that were active, newest last.
+///
+/// Desktops are kept along with their GUID and name, as their index changes when desktops before
+/// them are created, removed or moved.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DesktopHistory {
+    current: Option<DesktopInfo>,
+    back: VecDeque<DesktopInfo>,
+    forward: Vec<DesktopInfo>,
+    depth: usize,
+}
+
+/// The current index of the desktop `entry` was recorded for: the one with the same GUID, or where
+/// there are none, the one with the same name that is closest to the recorded index.
+fn find_entry(desktops: &[DesktopInfo], entry: &DesktopInfo) -> Option<u32> {
+    if let Some(guid) = &entry.guid {
+        let guid = normalize_guid(guid);
+        return desktops
+            .iter()
+            .find(|desktop| {
+                desktop
+                    .guid
+                    .as_deref()
+                    .is_some_and(|it| normalize_guid(it) == guid)
+            })
+            .map(|desktop| desktop.index);
+    }
+    desktops
+        .iter()
+        .filter(|desktop| desktop.name == entry.name)
+        .min_by_key(|desktop| desktop.index.abs_diff(entry.index))
+        .map(|desktop| desktop.index)
+}
+
+impl DesktopHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            current: None,
+            back: VecDeque::new(),
+            forward: vec![],
+            depth,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    // Drops the oldest entries in both directions, always keeping the last used desktop
+    fn trim(&mut self) {
+        let depth = self.depth.max(1);
+        while self.back.len() > depth {
+            self.back.pop_front();
+        }
+        let excess = self.forward.len().saturating_sub(depth);
+        self.forward.drain(..excess);
+    }
+
+    /// Records a newly active desktop, which like following a link discards the forward history.
+    ///
+    /// The same desktop under a new name only updates the current entry.
+    pub fn visit(&mut self, info: DesktopInfo) {
+        let is_current = self
+            .current
+            .as_ref()
+            .is_some_and(|current| match &info.guid {
+                Some(guid) => {
+                    current.guid.as_deref().map(normalize_guid) == Some(normalize_guid(guid))
+                }
+                None => current.index == info.index,
+            });
+        if is_current {
+            self.current = Some(info);
+            return;
+        }
+        if let Some(current) = self.current.replace(info) {
+            self.back.push_back(current);
+            self.trim();
+        }
+        self.forward.clear();
+    }
+
+    /// The desktop that was active before the current one, so two desktops can be toggled.
+    pub fn last_used(&self, desktops: &[DesktopInfo]) -> Option<u32> {
+        find_entry(desktops, self.back.back()?)
+    }
+
+    /// The desktop one step back, which only becomes current once `go_back` is called.
+    pub fn back_target(&self, desktops: &[DesktopInfo]) -> Option<u32> {
+        self.last_used(desktops)
+    }
+
+    /// The desktop one step forward, which only becomes current once `go_forward` is called.
+    pub fn forward_target(&self, desktops: &[DesktopInfo]) -> Option<u32> {
+        find_entry(desktops, self.forward.last()?)
+    }
+
+    /// Steps back, after switching to `back_target` succeeded.
+    ///
+    /// Call it before the change event caused by the switch is recorded, which then isn't a visit.
+    pub fn go_back(&mut self) {
+        let Some(target) = self.back.pop_back() else {
+            return;
+        };
+        if let Some(current) = self.current.replace(target) {
+            self.forward.push(current);
+        }
+    }
+
+    /// Steps forward again after `go_back`, after switching to `forward_target` succeeded.
+    pub fn go_forward(&mut self) {
+        let Some(target) = self.forward.pop() else {
+            return;
+        };
+        if let Some(current) = self.current.replace(target) {
+            self.back.push_back(current);
+            self.trim();
+        }
+    }
+}
+
+/// History shared between the thread that records it and the hotkeys that navigate it.
+#[derive(Clone, Debug)]
+pub struct SharedHistory(Arc<Mutex<DesktopHistory>>);
+
+impl SharedHistory {
+    pub fn new(depth: usize) -> Self {
+        Self(Arc::new(Mutex::new(DesktopHistory::new(depth))))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, DesktopHistory> {
+        self.0.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// Records every change of the active desktop into `history`.
+pub fn spawn_history_tracker<B: VirtualDesktopBackend>(
+    desktop_event_hooks: DesktopEventHooks<B>,
+    history: SharedHistory,
+) {
+    thread::spawn(move || {
+        desktop_event_hooks.on_active_desktop_change(|info: DesktopInfo| {
+            history.lock().visit(info);
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desktop(index: u32, name: &str) -> DesktopInfo {
+        DesktopInfo {
+            name: name.to_string(),
+            index,
+            guid: None,
+        }
+    }
+
+    fn desktops(names: &[&str]) -> Vec<DesktopInfo> {
+        (0..names.len() as u32)
+            .map(|index| desktop(index, names[index as usize]))
+            .collect()
+    }
+
+    fn visited(names: &[&str], depth: usize) -> DesktopHistory {
+        let mut history = DesktopHistory::new(depth);
+        for (index, name) in names.iter().enumerate() {
+            history.visit(desktop(index as u32, name));
+        }
+        history
+    }
+
+    #[test]
+    fn visits_record_the_previous_desktop() {
+        let all = desktops(&["A", "B", "C"]);
+        let mut history = visited(&["A", "B"], 10);
+        assert_eq!(history.last_used(&all), Some(0));
+
+        // Staying on a desktop, or renaming it, is no visit
+        history.visit(desktop(1, "B"));
+        history.visit(desktop(1, "Work"));
+        assert_eq!(history.last_used(&all), Some(0));
+        assert_eq!(history.current, Some(desktop(1, "Work")));
+    }
+
+    #[test]
+    fn goes_back_and_forward() {
+        let all = desktops(&["A", "B", "C"]);
+        let mut history = visited(&["A", "B", "C"], 10);
+        assert_eq!(history.forward_target(&all), None);
+
+        assert_eq!(history.back_target(&all), Some(1));
+        history.go_back();
+        assert_eq!(history.back_target(&all), Some(0));
+        history.go_back();
+        assert_eq!(history.back_target(&all), None);
+        assert_eq!(history.forward_target(&all), Some(1));
+        history.go_forward();
+        assert_eq!(history.forward_target(&all), Some(2));
+
+        // A visit discards the way forward
+        history.visit(desktop(0, "A"));
+        assert_eq!(history.forward_target(&all), None);
+        assert_eq!(history.back_target(&all), Some(1));
+    }
+
+    #[test]
+    fn targets_leave_the_history_as_it_is() {
+        let all = desktops(&["A", "B"]);
+        let history = visited(&["A", "B"], 10);
+        let before = history.clone();
+        history.back_target(&all);
+        history.forward_target(&all);
+        assert_eq!(history, before);
+    }
+
+    #[test]
+    fn keeps_only_the_newest_entries() {
+        let all = desktops(&["A", "B", "C", "D"]);
+        let mut history = visited(&["A", "B", "C", "D"], 2);
+        history.go_back();
+        history.go_back();
+        assert_eq!(history.back_target(&all), None);
+
+        history.set_depth(1);
+        assert_eq!(history.forward_target(&all), Some(2));
+        history.go_forward();
+        assert_eq!(history.forward_target(&all), None);
+    }
+
+    #[test]
+    fn always_remembers_the_last_used_desktop() {
+        let all = desktops(&["A", "B", "C"]);
+        let mut history = visited(&["A", "B", "C"], 0);
+        assert_eq!(history.last_used(&all), Some(1));
+        history.go_back();
+        assert_eq!(history.back_target(&all), None);
+
+        // Stepping forward again after the history got shorter doesn't outgrow it
+        history = visited(&["A", "B", "C"], 2);
+        history.go_back();
+        history.set_depth(1);
+        history.go_forward();
+        assert_eq!(history.back.len(), 1);
+        assert_eq!(history.last_used(&all), Some(1));
+    }
+
+    #[test]
+    fn follows_desktops_to_their_new_index() {
+        let mut history = visited(&["A", "B", "C"], 10);
+        // `A` was removed, so the others moved down
+        assert_eq!(history.last_used(&desktops(&["B", "C"])), Some(0));
+        history.go_back();
+        assert_eq!(history.back_target(&desktops(&["B", "C"])), None);
+
+        let with_guid = |index, guid: &str| DesktopInfo {
+            guid: Some(guid.to_string()),
+            ..desktop(index, "Same")
+        };
+        history = DesktopHistory::new(10);
+        history.visit(with_guid(0, "{aa}"));
+        history.visit(with_guid(1, "{bb}"));
+        assert_eq!(
+            history.last_used(&[with_guid(0, "BB"), with_guid(1, "AA")]),
+            Some(1)
+        );
+    }
+}
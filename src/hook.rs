@@ -0,0 +1,37 @@
+#[cfg(not(windows))]
+mod fallback;
+#[cfg(windows)]
+mod win;
+
+use std::fmt::{Display, Formatter};
+
+#[cfg(not(windows))]
+pub use fallback::{check_available, set_key_handler};
+#[cfg(windows)]
+pub use win::{check_available, set_key_handler};
+
+/// Why a key combination can't be used as a hotkey.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum RegistrationError {
+    /// Another program registered the same key combination as a hotkey.
+    #[cfg_attr(not(windows), allow(unused))]
+    TakenByOtherProgram,
+    /// Hotkeys aren't implemented on this platform.
+    #[cfg_attr(windows, allow(unused))]
+    Unsupported,
+}
+
+impl Display for RegistrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::TakenByOtherProgram => write!(f, "used by another program"),
+            RegistrationError::Unsupported => write!(f, "not supported on this platform"),
+        }
+    }
+}
+
+/// Called with the virtual-key code of every key pressed anywhere, and a check for whether a key is
+/// held along with it. Returns whether to keep the key from the focused application.
+///
+/// Runs while the key press waits to be delivered, so it must return quickly.
+pub type KeyHandler = Box<dyn FnMut(u16, &dyn Fn(u16) -> bool) -> bool + Send>;
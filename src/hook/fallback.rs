@@ -0,0 +1,10 @@
+use crate::hook::{KeyHandler, RegistrationError};
+use crate::keys::KeyCombo;
+
+/// Global hotkeys are only implemented on Windows, so elsewhere no key ever reaches the handler.
+pub fn set_key_handler(_handler: Option<KeyHandler>) {}
+
+/// Rejects every key combination, so that the tray lists the hotkeys that won't work.
+pub fn check_available(_combo: &KeyCombo) -> Result<(), RegistrationError> {
+    Err(RegistrationError::Unsupported)
+}
@@ -0,0 +1,96 @@
+use crate::hook::{KeyHandler, RegistrationError};
+use crate::keys::KeyCombo;
+use std::sync::{Mutex, MutexGuard, Once};
+use std::thread;
+use win_hotkeys::hook::{self, KeyAction, KeyboardEvent};
+use win_hotkeys::VKey;
+use windows::Win32::Foundation::{ERROR_HOTKEY_ALREADY_REGISTERED, HWND};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT,
+    MOD_SHIFT, MOD_WIN,
+};
+
+/// Id of the hotkey registered while probing, the highest one an application may use.
+const PROBE_HOTKEY_ID: i32 = 0xBFFF;
+
+static KEY_HANDLER: Mutex<Option<KeyHandler>> = Mutex::new(None);
+static START_HOOK: Once = Once::new();
+
+fn lock_handler() -> MutexGuard<'static, Option<KeyHandler>> {
+    KEY_HANDLER.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Hands every key press to `handler` from now on, or lets every key through if it's `None`.
+///
+/// The hook is installed on first use and kept until the app exits. `win_hotkeys` keeps its state
+/// in statics, so a second hook would take them over, and while one is restarted keys slip through.
+pub fn set_key_handler(handler: Option<KeyHandler>) {
+    *lock_handler() = handler;
+    START_HOOK.call_once(|| {
+        thread::spawn(run_hook);
+    });
+}
+
+fn run_hook() {
+    let hook = hook::start();
+    while let Ok(event) = hook.recv() {
+        let KeyboardEvent::KeyDown {
+            vk_code,
+            keyboard_state,
+        } = event
+        else {
+            continue;
+        };
+        let swallow = match lock_handler().as_mut() {
+            Some(handler) => handler(vk_code, &|key| keyboard_state.is_down(key)),
+            None => false,
+        };
+        let action = if !swallow {
+            KeyAction::Allow
+        } else if keyboard_state.is_down(VKey::LWin.to_vk_code()) {
+            // Like `win_hotkeys` does, so that releasing Win doesn't open the start menu
+            KeyAction::Replace
+        } else {
+            KeyAction::Block
+        };
+        hook.key_action(action);
+    }
+}
+
+/// Checks whether another program registered `combo` as a hotkey, by briefly registering it too.
+///
+/// The hook sees keys before such hotkeys do, so it would take them from that program unnoticed.
+pub fn check_available(combo: &KeyCombo) -> Result<(), RegistrationError> {
+    let modifiers = combo
+        .modifier_keys()
+        .iter()
+        .fold(MOD_NOREPEAT, |modifiers, key| {
+            modifiers
+                | match key.vk_code {
+                    0x11 | 0xA2 | 0xA3 => MOD_CONTROL,
+                    0x12 | 0xA4 | 0xA5 => MOD_ALT,
+                    0x10 | 0xA0 | 0xA1 => MOD_SHIFT,
+                    0x5B | 0x5C => MOD_WIN,
+                    _ => HOT_KEY_MODIFIERS(0),
+                }
+        });
+    let vk_code = combo.trigger_key().vk_code.into();
+
+    let result = unsafe { RegisterHotKey(HWND::default(), PROBE_HOTKEY_ID, modifiers, vk_code) };
+    match result {
+        Ok(()) => {
+            if let Err(error) = unsafe { UnregisterHotKey(HWND::default(), PROBE_HOTKEY_ID) } {
+                log::warn!("Failed to release hotkey {} after probing: {}", combo, error);
+            }
+            Ok(())
+        }
+        Err(error) if error.code() == ERROR_HOTKEY_ALREADY_REGISTERED.to_hresult() => {
+            Err(RegistrationError::TakenByOtherProgram)
+        }
+        Err(error) => {
+            // Such as keys that can't be registered at all, which the hook can still take
+            log::debug!("Could not probe hotkey {}: {}", combo, error);
+            Ok(())
+        }
+    }
+}
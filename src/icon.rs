@@ -1,53 +1,554 @@
-use crate::config::Settings;
+use crate::config::{DesktopPattern, GeneratedIcons, IconLabel, IconShape, Settings};
+use crate::desktop::{normalize_guid, DesktopInfo};
 use crate::guard_clause;
+use crate::render::{
+    can_render, encode_ico, render_label, to_ico, Color, LabelStyle, Shape, ICON_SIZES,
+};
+use regex::Regex;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use trayicon::Icon;
 
-#[derive(Clone, Debug)]
+const BUILT_IN_ICON: &[u8] = include_bytes!("../assets/default.ico");
+
+#[derive(Debug)]
 pub struct IconSelector {
-    default_icon: Option<Arc<Icon>>,
+    default_icon: Arc<Icon>,
     index_to_icon: Arc<HashMap<u32, Option<Arc<Icon>>>>,
+    /// Ordered by precedence, see `IconSettings::rules`.
+    rules: Vec<(DesktopMatcher, Option<Arc<Icon>>)>,
+    generator: Option<IconGenerator>,
 }
 
 impl IconSelector {
-    pub fn new(settings: &Settings) -> Self {
-        let default_icon = load_icon(&settings.default_icon_path);
+    /// Uses the icons of the dark theme variant if `system_dark` is set, see `IconSettings::active_set`.
+    pub fn new(settings: &Settings, system_dark: bool) -> Self {
+        let icons = settings.icons.active_set(system_dark);
+
+        // Desktops sharing an icon file only read and convert it once
+        let mut path_to_icon: HashMap<String, Option<Arc<Icon>>> = HashMap::new();
+        let mut load = |path: &str| {
+            path_to_icon
+                .entry(path.to_string())
+                .or_insert_with(|| load_icon(path))
+                .clone()
+        };
+
+        let default_icon = icons
+            .default
+            .as_deref()
+            .and_then(&mut load)
+            .unwrap_or_else(built_in_icon);
         let index_to_icon = Arc::new(
-            settings
-                .desktop_index_to_icon_path
+            icons
+                .desktops
                 .iter()
-                .map(|(index, path)| (*index, load_icon(&path)))
+                .map(|(index, path)| (*index, load(path)))
                 .collect::<HashMap<_, _>>(),
         );
 
-        let selector = Self {
+        let mut rules: Vec<_> = icons
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let matcher = guard_clause!(DesktopMatcher::new(&rule.desktop), error, {
+                    log::error!("Ignoring icon rule for '{}': {}", rule.icon, error);
+                    return None;
+                });
+                Some((matcher, load(&rule.icon)))
+            })
+            .collect();
+        // Stable, so rules of the same kind keep their order
+        rules.sort_by_key(|(matcher, _)| matcher.rank());
+
+        let generator = icons.generated.as_ref().map(IconGenerator::new);
+
+        Self {
             default_icon,
             index_to_icon,
-        };
-
-        selector
+            rules,
+            generator,
+        }
     }
 
-    pub fn get_by_index(&self, index: u32) -> Option<Arc<Icon>> {
+    fn get_by_index(&self, index: u32) -> Option<Arc<Icon>> {
         self.index_to_icon.get(&index)?.clone()
     }
 
-    pub fn get_default(&self) -> Option<Arc<Icon>> {
+    fn get_by_rules(&self, info: &DesktopInfo) -> Option<Arc<Icon>> {
+        self.rules
+            .iter()
+            .filter(|(matcher, _)| matcher.matches(info))
+            .find_map(|(_, icon)| icon.clone())
+    }
+
+    /// Picks the icon file of the first matching rule or of the desktop index, or else a generated
+    /// icon if enabled, or else the default.
+    pub fn get(&mut self, info: &DesktopInfo) -> Arc<Icon> {
+        if let Some(icon) = self
+            .get_by_rules(info)
+            .or_else(|| self.get_by_index(info.index))
+        {
+            return icon;
+        }
+        self.generator
+            .as_mut()
+            .and_then(|generator| generator.get(info))
+            .unwrap_or_else(|| self.default_icon.clone())
+    }
+
+    pub fn get_default(&self) -> Arc<Icon> {
         self.default_icon.clone()
     }
 }
 
+/// The desktops a `DesktopPattern` stands for, ready to be matched.
+#[derive(Debug)]
+pub enum DesktopMatcher {
+    Guid(String),
+    Name(String),
+    NamePattern(Regex),
+}
+
+impl DesktopMatcher {
+    pub fn new(pattern: &DesktopPattern) -> Result<Self, regex::Error> {
+        let matcher = match pattern {
+            DesktopPattern::Guid(guid) => Self::Guid(normalize_guid(guid)),
+            DesktopPattern::Name(name) if !name.contains(['*', '?']) => Self::Name(name.clone()),
+            DesktopPattern::Name(glob) => Self::NamePattern(Regex::new(&glob_to_regex(glob))?),
+            DesktopPattern::Regex(regex) => Self::NamePattern(Regex::new(regex)?),
+        };
+        Ok(matcher)
+    }
+
+    /// Precedence of the kind of rule, the lowest winning.
+    fn rank(&self) -> u8 {
+        match self {
+            DesktopMatcher::Guid(_) => 0,
+            DesktopMatcher::Name(_) => 1,
+            DesktopMatcher::NamePattern(_) => 2,
+        }
+    }
+
+    pub fn matches(&self, info: &DesktopInfo) -> bool {
+        match self {
+            DesktopMatcher::Guid(guid) => info
+                .guid
+                .as_deref()
+                .is_some_and(|it| normalize_guid(it) == *guid),
+            DesktopMatcher::Name(name) => info.name == *name,
+            DesktopMatcher::NamePattern(regex) => regex.is_match(&info.name),
+        }
+    }
+}
+
+/// Turns a glob like `Work*` into a regular expression matching the whole name.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for char in glob.chars() {
+        match char {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(char.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+fn built_in_icon() -> Arc<Icon> {
+    let icon = icon_from_buffer(BUILT_IN_ICON.to_vec(), "built-in icon");
+    icon.expect("Built-in icon should be a valid icon file")
+}
+
 fn load_icon(path: &str) -> Option<Arc<Icon>> {
     let buffer = guard_clause!(fs::read(path), error, {
         log::error!("Failed to read icon file '{}': {}", path, error);
         return None;
     });
-    let buffer: &'static [u8] = Box::leak(buffer.into_boxed_slice());
-    let icon = guard_clause!(Icon::from_buffer(buffer, None, None), error, {
-        log::error!("Failed to load icon from '{}': {}", path, error);
+    let buffer = guard_clause!(to_ico(buffer), error, {
+        log::error!("Failed to convert icon file '{}': {}", path, error);
         return None;
     });
-    Some(Arc::new(icon))
+    icon_from_buffer(buffer, path)
+}
+
+thread_local! {
+    /// Icons by the contents of their ICO file, shared by every selector on the tray thread.
+    ///
+    /// The tray only takes icons from `'static` buffers, so every distinct buffer is kept until the
+    /// app exits, but only once however often the settings are reloaded. The icons themselves are
+    /// held weakly, and released along with the last selector using them.
+    static ICON_CACHE: RefCell<HashMap<&'static [u8], Weak<Icon>>> = RefCell::new(HashMap::new());
+}
+
+/// Hands an ICO file to the tray, with `source` naming where it came from in errors.
+fn icon_from_buffer(buffer: Vec<u8>, source: &str) -> Option<Arc<Icon>> {
+    ICON_CACHE.with_borrow_mut(|cache| {
+        let buffer: &'static [u8] = match cache.get_key_value(buffer.as_slice()) {
+            Some((cached_buffer, icon)) => match icon.upgrade() {
+                Some(icon) => return Some(icon),
+                None => cached_buffer,
+            },
+            None => Box::leak(buffer.into_boxed_slice()),
+        };
+
+        let icon = guard_clause!(Icon::from_buffer(buffer, None, None), error, {
+            log::error!("Failed to load icon from '{}': {}", source, error);
+            // Kept as well, so that retrying a broken file doesn't leak it again
+            cache.insert(buffer, Weak::new());
+            return None;
+        });
+        let icon = Arc::new(icon);
+        cache.insert(buffer, Arc::downgrade(&icon));
+        Some(icon)
+    })
+}
+
+/// Draws icons showing the desktop number or initials, keeping each label drawn so far.
+#[derive(Debug)]
+struct IconGenerator {
+    label: IconLabel,
+    style: LabelStyle,
+    label_to_icon: HashMap<String, Arc<Icon>>,
+}
+
+fn parse_color(color: &str, fallback: &str) -> Color {
+    Color::parse(color).unwrap_or_else(|| {
+        log::error!("Invalid icon color '{}', using {} instead", color, fallback);
+        Color::parse(fallback).expect("Fallback color should be valid")
+    })
+}
+
+impl IconGenerator {
+    fn new(settings: &GeneratedIcons) -> Self {
+        let defaults = GeneratedIcons::default();
+        let style = LabelStyle {
+            font_size: settings.font_size,
+            foreground: parse_color(&settings.foreground, &defaults.foreground),
+            background: parse_color(&settings.background, &defaults.background),
+            shape: match settings.shape {
+                IconShape::Square => Shape::Square,
+                IconShape::Rounded => Shape::Rounded,
+                IconShape::Circle => Shape::Circle,
+            },
+        };
+        Self {
+            label: settings.label,
+            style,
+            label_to_icon: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, info: &DesktopInfo) -> Option<Arc<Icon>> {
+        let label = label_text(self.label, info);
+        if let Some(icon) = self.label_to_icon.get(&label) {
+            return Some(icon.clone());
+        }
+
+        let images: Vec<_> = ICON_SIZES
+            .iter()
+            .map(|size| render_label(&label, *size, &self.style))
+            .collect();
+        let icon = icon_from_buffer(encode_ico(&images), &format!("generated icon '{}'", label))?;
+        self.label_to_icon.insert(label, icon.clone());
+        Some(icon)
+    }
+}
+
+/// The desktop number, or the first letters of its name: of the first two words, or of the
+/// only word.
+pub fn label_text(label: IconLabel, info: &DesktopInfo) -> String {
+    let number = (info.index + 1).to_string();
+    if label == IconLabel::Number {
+        return number;
+    }
+
+    let words: Vec<Vec<char>> = info
+        .name
+        .split_whitespace()
+        .map(|word| word.chars().filter(|char| can_render(*char)).collect())
+        .filter(|word: &Vec<char>| !word.is_empty())
+        .collect();
+    let initials: String = match words.as_slice() {
+        [] => return number,
+        [word] => word.iter().take(2).collect(),
+        words => words.iter().take(2).map(|word| word[0]).collect(),
+    };
+    initials.to_uppercase()
+}
+
+/// Checks that `buffer` holds a well-formed ICO file without handing it to the tray.
+pub fn check_icon_buffer(buffer: &[u8]) -> Result<(), &'static str> {
+    let read_u16 = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            buffer[offset],
+            buffer[offset + 1],
+            buffer[offset + 2],
+            buffer[offset + 3],
+        ])
+    };
+
+    // ICONDIR header: reserved (always 0), resource type (1 for icons) and image count
+    if buffer.len() < 6 || read_u16(0) != 0 || read_u16(2) != 1 {
+        return Err("not an ICO file");
+    }
+    let image_count = read_u16(4) as usize;
+    if image_count == 0 {
+        return Err("contains no images");
+    }
+    if buffer.len() < 6 + image_count * 16 {
+        return Err("image directory is truncated");
+    }
+
+    for entry in 0..image_count {
+        let entry_offset = 6 + entry * 16;
+        let image_size = read_u32(entry_offset + 8) as usize;
+        let image_offset = read_u32(entry_offset + 12) as usize;
+        if image_size == 0 || image_offset.saturating_add(image_size) > buffer.len() {
+            return Err("image data is truncated");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GeneratedIcons, IconRule};
+
+    /// How many buffers are kept, and how many icons of those are still in use.
+    fn cached_icons() -> (usize, usize) {
+        ICON_CACHE.with_borrow(|cache| {
+            let live = cache.values().filter(|icon| icon.strong_count() > 0);
+            (cache.len(), live.count())
+        })
+    }
+
+    fn desktop(index: u32) -> DesktopInfo {
+        DesktopInfo {
+            name: format!("Desktop {}", index + 1),
+            index,
+            guid: None,
+        }
+    }
+
+    #[test]
+    fn shares_icons_with_the_same_contents() {
+        let first = icon_from_buffer(BUILT_IN_ICON.to_vec(), "first").unwrap();
+        let second = icon_from_buffer(BUILT_IN_ICON.to_vec(), "second").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cached_icons(), (1, 1));
+
+        drop((first, second));
+        assert_eq!(cached_icons(), (1, 0));
+        // The buffer kept from before is used again
+        let _third = icon_from_buffer(BUILT_IN_ICON.to_vec(), "third").unwrap();
+        assert_eq!(cached_icons(), (1, 1));
+    }
+
+    #[test]
+    fn rebuilding_selectors_keeps_each_buffer_once() {
+        let mut settings = Settings::default();
+        settings.icons.generated = Some(GeneratedIcons::default());
+
+        let mut cache_sizes = vec![];
+        for _ in 0..20 {
+            let mut selector = IconSelector::new(&settings, false);
+            for index in 0..4 {
+                selector.get(&desktop(index));
+            }
+            drop(selector);
+            cache_sizes.push(cached_icons());
+        }
+        // The built-in default and one icon per desktop, none of which are in use anymore
+        assert_eq!(cache_sizes, [(5, 0); 20]);
+    }
+
+    #[test]
+    fn labels_desktops_by_number_or_initials() {
+        let cases = [
+            (IconLabel::Number, 0, "Work", "1"),
+            (IconLabel::Number, 11, "Work", "12"),
+            (IconLabel::Initials, 0, "work", "WO"),
+            (IconLabel::Initials, 0, "W", "W"),
+            (IconLabel::Initials, 0, "  web   dev  ", "WD"),
+            (
+                IconLabel::Initials,
+                0,
+                "Development Environment For Projects",
+                "DE",
+            ),
+            (IconLabel::Initials, 0, "Supercalifragilistic", "SU"),
+            // Characters the font lacks are left out, along with words made only of them
+            (IconLabel::Initials, 0, "Über 🎵 mix", "BM"),
+            (IconLabel::Initials, 0, "#1-dev", "1D"),
+            (IconLabel::Initials, 2, "", "3"),
+            (IconLabel::Initials, 2, "🎵 — 日本", "3"),
+        ];
+        for (label, index, name, expected) in cases {
+            let info = DesktopInfo {
+                name: name.to_string(),
+                index,
+                guid: None,
+            };
+            assert_eq!(label_text(label, &info), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn globs_match_whole_names_literally() {
+        let cases = [
+            ("Work*", "^Work.*$"),
+            ("?", "^.$"),
+            ("v1.2+(beta)", r"^v1\.2\+\(beta\)$"),
+            ("[a-z]|^$", r"^\[a\-z\]\|\^\$$"),
+        ];
+        for (glob, expected) in cases {
+            assert_eq!(glob_to_regex(glob), expected, "{}", glob);
+        }
+
+        let matches = |glob: &str, name: &str| {
+            let matcher = DesktopMatcher::new(&DesktopPattern::Name(glob.to_string())).unwrap();
+            matcher.matches(&DesktopInfo {
+                name: name.to_string(),
+                index: 0,
+                guid: None,
+            })
+        };
+        let cases = [
+            ("v1.?", "v1.2", true),
+            ("v1.?", "v1x2", false),
+            ("v1.*", "v102", false),
+            ("C++ (*)", "C++ (old)", true),
+            ("C++ (*)", "CC+ (old)", false),
+            ("Work*", "Work", true),
+            ("Work*", "My Work", false),
+            ("*Work", "Work stuff", false),
+            ("Work?", "Work", false),
+            ("work*", "Work", false),
+            ("WORK", "Work", false),
+        ];
+        for (glob, name, expected) in cases {
+            assert_eq!(matches(glob, name), expected, "{} on {}", glob, name);
+        }
+    }
+
+    const GUID: &str = "0A1B2C3D-4E5F-6071-8293-A4B5C6D7E8F9";
+
+    /// One icon file per precedence level, each with different contents so they aren't shared.
+    struct Precedence {
+        _dir: tempfile::TempDir,
+        selector: IconSelector,
+        icons: HashMap<&'static str, Arc<Icon>>,
+    }
+
+    impl Precedence {
+        fn new(generated: bool) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let style = IconGenerator::new(&GeneratedIcons::default()).style;
+            let mut paths = HashMap::new();
+            for (level, label) in [
+                ("guid", "G"),
+                ("name", "N"),
+                ("pattern", "P"),
+                ("index", "I"),
+                ("default", "D"),
+            ] {
+                let path = dir.path().join(format!("{}.ico", level));
+                fs::write(&path, encode_ico(&[render_label(label, 16, &style)])).unwrap();
+                paths.insert(level, path.display().to_string());
+            }
+
+            let mut settings = Settings::default();
+            let icons = &mut settings.icons;
+            icons.default = Some(paths["default"].clone());
+            icons.desktops.insert(2, paths["index"].clone());
+            // Listed from the lowest precedence up, which the selector reorders
+            let rules = [
+                (DesktopPattern::Regex("^Work".to_string()), "pattern"),
+                (DesktopPattern::Name("Work".to_string()), "name"),
+                (DesktopPattern::Guid(format!("{{{}}}", GUID)), "guid"),
+            ];
+            for (desktop, level) in rules {
+                let icon = paths[level].clone();
+                icons.rules.push(IconRule { desktop, icon });
+            }
+            if generated {
+                icons.generated = Some(GeneratedIcons::default());
+            }
+
+            let selector = IconSelector::new(&settings, false);
+            let icons = paths
+                .iter()
+                .map(|(level, path)| (*level, load_icon(path).unwrap()))
+                .collect();
+            Self {
+                _dir: dir,
+                selector,
+                icons,
+            }
+        }
+
+        fn pick(&mut self, name: &str, index: u32, guid: Option<&str>) -> Arc<Icon> {
+            self.selector.get(&DesktopInfo {
+                name: name.to_string(),
+                index,
+                guid: guid.map(str::to_string),
+            })
+        }
+
+        fn assert_picks(&mut self, level: &str, name: &str, index: u32, guid: Option<&str>) {
+            let icon = self.pick(name, index, guid);
+            assert!(Arc::ptr_eq(&icon, &self.icons[level]), "expected {}", level);
+        }
+    }
+
+    #[test]
+    fn guid_rules_come_first() {
+        let mut precedence = Precedence::new(true);
+        let guid = GUID.to_ascii_lowercase();
+        precedence.assert_picks("guid", "Work", 2, Some(&guid));
+    }
+
+    #[test]
+    fn name_rules_come_before_patterns() {
+        let mut precedence = Precedence::new(true);
+        precedence.assert_picks(
+            "name",
+            "Work",
+            2,
+            Some("00000000-0000-0000-0000-000000000000"),
+        );
+    }
+
+    #[test]
+    fn patterns_come_before_desktop_indices() {
+        let mut precedence = Precedence::new(true);
+        precedence.assert_picks("pattern", "Workshop", 2, None);
+    }
+
+    #[test]
+    fn desktop_indices_come_before_generated_icons() {
+        let mut precedence = Precedence::new(true);
+        precedence.assert_picks("index", "Home", 2, None);
+    }
+
+    #[test]
+    fn generated_icons_come_before_the_default() {
+        let mut precedence = Precedence::new(true);
+        let icon = precedence.pick("Home", 3, None);
+        let mut generator = IconGenerator::new(&GeneratedIcons::default());
+        let generated = generator.get(&desktop(3)).unwrap();
+        assert!(Arc::ptr_eq(&icon, &generated));
+    }
+
+    #[test]
+    fn the_default_comes_last() {
+        let mut precedence = Precedence::new(false);
+        precedence.assert_picks("default", "Home", 3, None);
+    }
 }
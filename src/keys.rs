@@ -0,0 +1,539 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A key that can be used in hotkeys, identified by its Windows virtual-key code.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct KeyDefinition {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub vk_code: u16,
+}
+
+const fn key(name: &'static str, aliases: &'static [&'static str], vk_code: u16) -> KeyDefinition {
+    KeyDefinition {
+        name,
+        aliases,
+        vk_code,
+    }
+}
+
+/// Every key name accepted in `trigger_key` and `modifier_keys`: the Windows virtual-key constants
+/// without their `VK_` prefix and underscores, along with more familiar aliases.
+pub const KEYS: &[KeyDefinition] = &[
+    key("Cancel", &[], 0x03),
+    key("Back", &["Backspace"], 0x08),
+    key("Tab", &[], 0x09),
+    key("Clear", &[], 0x0C),
+    key("Return", &["Enter"], 0x0D),
+    key("Shift", &[], 0x10),
+    key("Control", &["Ctrl"], 0x11),
+    key("Menu", &["Alt"], 0x12),
+    key("Pause", &[], 0x13),
+    key("Capital", &["CapsLock"], 0x14),
+    key("Kana", &["Hangul", "Hangeul"], 0x15),
+    key("Junja", &[], 0x17),
+    key("Final", &[], 0x18),
+    key("Hanja", &["Kanji"], 0x19),
+    key("Escape", &["Esc"], 0x1B),
+    key("Convert", &[], 0x1C),
+    key("NonConvert", &[], 0x1D),
+    key("Accept", &[], 0x1E),
+    key("ModeChange", &[], 0x1F),
+    key("Space", &[], 0x20),
+    key("Prior", &["PageUp"], 0x21),
+    key("Next", &["PageDown"], 0x22),
+    key("End", &[], 0x23),
+    key("Home", &[], 0x24),
+    key("Left", &[], 0x25),
+    key("Up", &[], 0x26),
+    key("Right", &[], 0x27),
+    key("Down", &[], 0x28),
+    key("Select", &[], 0x29),
+    key("Print", &[], 0x2A),
+    key("Execute", &[], 0x2B),
+    key("Snapshot", &["PrintScreen"], 0x2C),
+    key("Insert", &["Ins"], 0x2D),
+    key("Delete", &["Del"], 0x2E),
+    key("Help", &[], 0x2F),
+    key("0", &[], 0x30),
+    key("1", &[], 0x31),
+    key("2", &[], 0x32),
+    key("3", &[], 0x33),
+    key("4", &[], 0x34),
+    key("5", &[], 0x35),
+    key("6", &[], 0x36),
+    key("7", &[], 0x37),
+    key("8", &[], 0x38),
+    key("9", &[], 0x39),
+    key("A", &[], 0x41),
+    key("B", &[], 0x42),
+    key("C", &[], 0x43),
+    key("D", &[], 0x44),
+    key("E", &[], 0x45),
+    key("F", &[], 0x46),
+    key("G", &[], 0x47),
+    key("H", &[], 0x48),
+    key("I", &[], 0x49),
+    key("J", &[], 0x4A),
+    key("K", &[], 0x4B),
+    key("L", &[], 0x4C),
+    key("M", &[], 0x4D),
+    key("N", &[], 0x4E),
+    key("O", &[], 0x4F),
+    key("P", &[], 0x50),
+    key("Q", &[], 0x51),
+    key("R", &[], 0x52),
+    key("S", &[], 0x53),
+    key("T", &[], 0x54),
+    key("U", &[], 0x55),
+    key("V", &[], 0x56),
+    key("W", &[], 0x57),
+    key("X", &[], 0x58),
+    key("Y", &[], 0x59),
+    key("Z", &[], 0x5A),
+    key("LWin", &["Win", "Super"], 0x5B),
+    key("RWin", &[], 0x5C),
+    key("Apps", &[], 0x5D),
+    key("Sleep", &[], 0x5F),
+    key("Numpad0", &[], 0x60),
+    key("Numpad1", &[], 0x61),
+    key("Numpad2", &[], 0x62),
+    key("Numpad3", &[], 0x63),
+    key("Numpad4", &[], 0x64),
+    key("Numpad5", &[], 0x65),
+    key("Numpad6", &[], 0x66),
+    key("Numpad7", &[], 0x67),
+    key("Numpad8", &[], 0x68),
+    key("Numpad9", &[], 0x69),
+    key("Multiply", &[], 0x6A),
+    key("Add", &[], 0x6B),
+    key("Separator", &[], 0x6C),
+    key("Subtract", &[], 0x6D),
+    key("Decimal", &[], 0x6E),
+    key("Divide", &[], 0x6F),
+    key("F1", &[], 0x70),
+    key("F2", &[], 0x71),
+    key("F3", &[], 0x72),
+    key("F4", &[], 0x73),
+    key("F5", &[], 0x74),
+    key("F6", &[], 0x75),
+    key("F7", &[], 0x76),
+    key("F8", &[], 0x77),
+    key("F9", &[], 0x78),
+    key("F10", &[], 0x79),
+    key("F11", &[], 0x7A),
+    key("F12", &[], 0x7B),
+    key("F13", &[], 0x7C),
+    key("F14", &[], 0x7D),
+    key("F15", &[], 0x7E),
+    key("F16", &[], 0x7F),
+    key("F17", &[], 0x80),
+    key("F18", &[], 0x81),
+    key("F19", &[], 0x82),
+    key("F20", &[], 0x83),
+    key("F21", &[], 0x84),
+    key("F22", &[], 0x85),
+    key("F23", &[], 0x86),
+    key("F24", &[], 0x87),
+    key("NumLock", &[], 0x90),
+    key("Scroll", &["ScrollLock"], 0x91),
+    key("LShift", &[], 0xA0),
+    key("RShift", &[], 0xA1),
+    key("LControl", &["LCtrl"], 0xA2),
+    key("RControl", &["RCtrl"], 0xA3),
+    key("LMenu", &["LAlt"], 0xA4),
+    key("RMenu", &["RAlt"], 0xA5),
+    key("BrowserBack", &[], 0xA6),
+    key("BrowserForward", &[], 0xA7),
+    key("BrowserRefresh", &[], 0xA8),
+    key("BrowserStop", &[], 0xA9),
+    key("BrowserSearch", &[], 0xAA),
+    key("BrowserFavorites", &[], 0xAB),
+    key("BrowserHome", &[], 0xAC),
+    key("VolumeMute", &[], 0xAD),
+    key("VolumeDown", &[], 0xAE),
+    key("VolumeUp", &[], 0xAF),
+    key("MediaNextTrack", &[], 0xB0),
+    key("MediaPrevTrack", &[], 0xB1),
+    key("MediaStop", &[], 0xB2),
+    key("MediaPlayPause", &[], 0xB3),
+    key("LaunchMail", &[], 0xB4),
+    key("LaunchMediaSelect", &[], 0xB5),
+    key("LaunchApp1", &[], 0xB6),
+    key("LaunchApp2", &[], 0xB7),
+    key("Oem1", &[], 0xBA),
+    key("OemPlus", &[], 0xBB),
+    key("OemComma", &[], 0xBC),
+    key("OemMinus", &[], 0xBD),
+    key("OemPeriod", &[], 0xBE),
+    key("Oem2", &[], 0xBF),
+    key("Oem3", &[], 0xC0),
+    key("Oem4", &[], 0xDB),
+    key("Oem5", &[], 0xDC),
+    key("Oem6", &[], 0xDD),
+    key("Oem7", &[], 0xDE),
+    key("Oem8", &[], 0xDF),
+    key("Oem102", &[], 0xE2),
+    key("ProcessKey", &[], 0xE5),
+    key("Packet", &[], 0xE7),
+    key("Attn", &[], 0xF6),
+    key("Crsel", &[], 0xF7),
+    key("Exsel", &[], 0xF8),
+    key("Ereof", &[], 0xF9),
+    key("Play", &[], 0xFA),
+    key("Zoom", &[], 0xFB),
+    key("Noname", &[], 0xFC),
+    key("Pa1", &[], 0xFD),
+    key("OemClear", &[], 0xFE),
+];
+
+/// Names and aliases of every key, in table order.
+pub fn key_names() -> impl Iterator<Item = &'static str> {
+    KEYS.iter()
+        .flat_map(|key| std::iter::once(key.name).chain(key.aliases.iter().copied()))
+}
+
+/// Looks up a key by name or alias, ignoring case and underscores, with or without the `VK_` prefix,
+/// so that the Windows constants such as `VK_OEM_PLUS` work too. A hex code such as `0x41` stands
+/// for the key with that virtual-key code.
+pub fn find_key(name: &str) -> Option<&'static KeyDefinition> {
+    if let Some(code) = name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+        let code = u16::from_str_radix(code, 16).ok()?;
+        return KEYS.iter().find(|key| key.vk_code == code);
+    }
+    let name = match name.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("VK_") => &name[3..],
+        _ => name,
+    };
+    let name: String = name.chars().filter(|char| *char != '_').collect();
+    KEYS.iter().find(|key| {
+        key.name.eq_ignore_ascii_case(&name)
+            || key
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(&name))
+    })
+}
+
+impl KeyDefinition {
+    /// The name used when displaying key combinations: the first alias, as it tends to be the
+    /// familiar one (`Alt` rather than `Menu`), or else the name.
+    pub fn display_name(&self) -> &'static str {
+        self.aliases.first().copied().unwrap_or(self.name)
+    }
+
+    /// Orders modifiers the way Windows displays them, e.g. `Ctrl+Alt+Shift+Win`.
+    fn modifier_rank(&self) -> (u8, u16) {
+        let rank = match self.vk_code {
+            0x11 | 0xA2 | 0xA3 => 0,
+            0x12 | 0xA4 | 0xA5 => 1,
+            0x10 | 0xA0 | 0xA1 => 2,
+            0x5B | 0x5C => 3,
+            _ => 4,
+        };
+        (rank, self.vk_code)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[allow(unused)]
+pub enum KeyError {
+    UnknownKey(String),
+    /// A `+`-separated combination with nothing between two separators, or nothing at all.
+    MissingKey(String),
+    /// A digit range other than one within `1-9`.
+    InvalidRange(String),
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::UnknownKey(name) => write!(f, "'{}' is not a valid key name", name),
+            KeyError::MissingKey(combo) => write!(f, "'{}' is missing a key name", combo),
+            KeyError::InvalidRange(range) => {
+                write!(f, "'{}' is not a range of digits within 1-9", range)
+            }
+        }
+    }
+}
+
+fn lookup_key(name: &str) -> Result<&'static KeyDefinition, KeyError> {
+    find_key(name.trim()).ok_or_else(|| KeyError::UnknownKey(name.trim().to_string()))
+}
+
+/// Looks up the `+`-separated `names`, which are part of `combo`.
+fn lookup_keys(combo: &str, names: &str) -> Result<Vec<&'static KeyDefinition>, KeyError> {
+    names
+        .split('+')
+        .map(|name| match name.trim() {
+            "" => Err(KeyError::MissingKey(combo.to_string())),
+            name => lookup_key(name),
+        })
+        .collect()
+}
+
+/// A key press such as `Alt+Shift+3`: a trigger key and the modifiers held along with it.
+///
+/// Modifiers are kept sorted and without duplicates, so combinations compare equal regardless of
+/// how they were written.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct KeyCombo {
+    trigger_key: &'static KeyDefinition,
+    modifier_keys: Vec<&'static KeyDefinition>,
+}
+
+impl KeyCombo {
+    pub fn new(
+        trigger_key: &'static KeyDefinition,
+        modifier_keys: impl IntoIterator<Item = &'static KeyDefinition>,
+    ) -> Self {
+        let mut modifier_keys: Vec<_> = modifier_keys.into_iter().collect();
+        modifier_keys.sort_by_key(|key| key.modifier_rank());
+        modifier_keys.dedup();
+        Self {
+            trigger_key,
+            modifier_keys,
+        }
+    }
+
+    /// Parses the separate `trigger_key` and `modifier_keys` fields of the settings.
+    pub fn from_names(trigger_key: &str, modifier_keys: &[String]) -> Result<Self, KeyError> {
+        let trigger_key = lookup_key(trigger_key)?;
+        let modifier_keys: Result<Vec<_>, _> =
+            modifier_keys.iter().map(|name| lookup_key(name)).collect();
+        Ok(Self::new(trigger_key, modifier_keys?))
+    }
+
+    // Only read by the Windows hotkeys outside of tests
+    #[cfg_attr(not(windows), allow(unused))]
+    pub fn trigger_key(&self) -> &'static KeyDefinition {
+        self.trigger_key
+    }
+
+    #[cfg_attr(not(windows), allow(unused))]
+    pub fn modifier_keys(&self) -> &[&'static KeyDefinition] {
+        &self.modifier_keys
+    }
+
+    /// Whether pressing the key `vk_code` while the keys for which `is_down` holds are down makes
+    /// this combination: its modifiers are held, and no other of Ctrl, Alt, Shift and Win is.
+    pub fn is_pressed(&self, vk_code: u16, is_down: impl Fn(u16) -> bool) -> bool {
+        if vk_code != self.trigger_key.vk_code
+            || !self.modifier_keys.iter().all(|key| is_down(key.vk_code))
+        {
+            return false;
+        }
+        MODIFIER_GROUPS.iter().all(|group| {
+            let expected = self
+                .modifier_keys
+                .iter()
+                .any(|key| group.contains(&key.vk_code));
+            expected == group.iter().any(|code| is_down(*code))
+        })
+    }
+}
+
+/// The virtual-key codes of each modifier, whichever side of the keyboard it's on.
+const MODIFIER_GROUPS: [&[u16]; 4] = [
+    &[0x11, 0xA2, 0xA3],
+    &[0x12, 0xA4, 0xA5],
+    &[0x10, 0xA0, 0xA1],
+    &[0x5B, 0x5C],
+];
+
+/// Parses `+`-separated key names, e.g. `ctrl+alt+Right`, where the last name is the trigger key.
+impl FromStr for KeyCombo {
+    type Err = KeyError;
+
+    fn from_str(combo: &str) -> Result<Self, Self::Err> {
+        let mut keys = lookup_keys(combo, combo)?;
+        let Some(trigger_key) = keys.pop() else {
+            return Err(KeyError::MissingKey(combo.to_string()));
+        };
+        Ok(Self::new(trigger_key, keys))
+    }
+}
+
+/// Writes the canonical form, e.g. `Ctrl+Alt+Right` for `alt+control+right`.
+impl Display for KeyCombo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for modifier_key in &self.modifier_keys {
+            write!(f, "{}+", modifier_key.display_name())?;
+        }
+        write!(f, "{}", self.trigger_key.display_name())
+    }
+}
+
+/// Parses a key combination that may end in a range of digits instead of a trigger key, e.g.
+/// `Alt+1-9`, returning one combination per digit along with that digit.
+pub fn parse_combo_range(combo: &str) -> Result<Vec<(KeyCombo, Option<u32>)>, KeyError> {
+    let (modifier_names, trigger) = match combo.rsplit_once('+') {
+        Some((modifier_names, trigger)) => (Some(modifier_names), trigger),
+        None => (None, combo),
+    };
+    // No key name contains a dash
+    let Some((first, last)) = trigger.split_once('-') else {
+        return Ok(vec![(combo.parse()?, None)]);
+    };
+    let digits = match (first.trim().parse::<u32>(), last.trim().parse::<u32>()) {
+        (Ok(first), Ok(last)) if 1 <= first && first <= last && last <= 9 => first..=last,
+        _ => return Err(KeyError::InvalidRange(trigger.trim().to_string())),
+    };
+
+    let modifier_keys = match modifier_names {
+        Some(modifier_names) => lookup_keys(combo, modifier_names)?,
+        None => vec![],
+    };
+    digits
+        .map(|digit| {
+            let trigger_key = lookup_key(&digit.to_string())?;
+            let combo = KeyCombo::new(trigger_key, modifier_keys.iter().copied());
+            Ok((combo, Some(digit)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combo(text: &str) -> KeyCombo {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_modifiers_and_trigger_key() {
+        let parsed = combo("Ctrl+Alt+Right");
+        assert_eq!(parsed.trigger_key().name, "Right");
+        let modifier_names: Vec<_> = parsed.modifier_keys().iter().map(|key| key.name).collect();
+        assert_eq!(modifier_names, ["Control", "Menu"]);
+        assert!(combo("F5").modifier_keys().is_empty());
+    }
+
+    #[test]
+    fn ignores_case_whitespace_and_modifier_order() {
+        assert_eq!(combo(" alt + CTRL+right "), combo("Ctrl+Alt+Right"));
+        assert_eq!(combo("Shift+Shift+Win+1"), combo("Super+Shift+1"));
+        assert_eq!(combo("Menu+D").to_string(), "Alt+D");
+    }
+
+    #[test]
+    fn accepts_windows_constant_names_and_codes() {
+        for name in ["VK_OEM_PLUS", "vk_oem_plus", "Oem_Plus", "0xBB", "0XBB"] {
+            assert_eq!(
+                find_key(name).map(|key| key.name),
+                Some("OemPlus"),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(combo("VK_CONTROL+VK_MENU+VK_1"), combo("Ctrl+Alt+1"));
+        assert_eq!(
+            find_key("MEDIA_PLAY_PAUSE").map(|key| key.vk_code),
+            Some(0xB3)
+        );
+        assert_eq!(find_key("0x07"), None);
+        assert_eq!(find_key("0xZZ"), None);
+        assert_eq!(find_key("VK_"), None);
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let names: Vec<String> = key_names().map(|name| name.to_ascii_uppercase()).collect();
+        for (index, name) in names.iter().enumerate() {
+            assert!(!names[..index].contains(name), "{} is listed twice", name);
+        }
+        for (index, key) in KEYS.iter().enumerate() {
+            assert!(KEYS[..index]
+                .iter()
+                .all(|other| other.vk_code != key.vk_code));
+        }
+    }
+
+    #[test]
+    fn reports_unknown_keys() {
+        assert_eq!(
+            "Ctrl+Nope".parse::<KeyCombo>(),
+            Err(KeyError::UnknownKey("Nope".to_string()))
+        );
+        assert_eq!(
+            KeyCombo::from_names("1", &[" Hyper ".to_string()]),
+            Err(KeyError::UnknownKey("Hyper".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_missing_keys() {
+        for text in ["", "Alt+", "Alt++1", "+1", " "] {
+            assert_eq!(
+                text.parse::<KeyCombo>(),
+                Err(KeyError::MissingKey(text.to_string())),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn reports_invalid_ranges() {
+        for (text, range) in [("Alt+0-9", "0-9"), ("Alt+5-3", "5-3"), ("A-Z", "A-Z")] {
+            assert_eq!(
+                parse_combo_range(text),
+                Err(KeyError::InvalidRange(range.to_string()))
+            );
+        }
+        assert_eq!(
+            KeyError::InvalidRange("0-9".to_string()).to_string(),
+            "'0-9' is not a range of digits within 1-9"
+        );
+    }
+
+    #[test]
+    fn expands_digit_ranges() {
+        let combos = parse_combo_range("Win+Shift+1-3").unwrap();
+        let expected: Vec<_> = (1..=3)
+            .map(|digit| (combo(&format!("Shift+Win+{}", digit)), Some(digit)))
+            .collect();
+        assert_eq!(combos, expected);
+        assert_eq!(
+            parse_combo_range("Alt+D").unwrap(),
+            [(combo("Alt+D"), None)]
+        );
+    }
+
+    #[test]
+    fn matches_presses_with_exactly_its_modifiers() {
+        // The generic modifiers are down along with either side, as Windows reports them
+        let cases: &[(&str, &[u16], bool)] = &[
+            ("Alt+1", &[0x12, 0xA4, 0x31], true),
+            ("Alt+1", &[0x12, 0xA5, 0x31], true),
+            ("Alt+1", &[0x31], false),
+            ("Alt+1", &[0x12, 0xA4, 0x10, 0xA0, 0x31], false),
+            ("LAlt+1", &[0x12, 0xA5, 0x31], false),
+            ("Win+1", &[0x5B, 0x31], true),
+            ("Alt+1", &[0x12, 0xA4, 0x5C, 0x31], false),
+            ("3", &[0x33], true),
+            ("3", &[0x11, 0xA2, 0x33], false),
+        ];
+        for (text, held, expected) in cases {
+            let pressed = combo(text).is_pressed(combo(text).trigger_key().vk_code, |code| {
+                held.contains(&code)
+            });
+            assert_eq!(pressed, *expected, "{} with {:02X?}", text, held);
+        }
+        assert!(!combo("Alt+1").is_pressed(0x32, |code| [0x12, 0x32].contains(&code)));
+    }
+
+    #[test]
+    fn display_round_trips_through_from_str() {
+        let modifier_keys = [combo("Ctrl").trigger_key(), combo("Shift").trigger_key()];
+        for key in KEYS {
+            for combo in [
+                KeyCombo::new(key, []),
+                KeyCombo::new(key, modifier_keys.iter().copied()),
+            ] {
+                assert_eq!(combo.to_string().parse(), Ok(combo.clone()), "{}", combo);
+            }
+        }
+    }
+}
@@ -1,34 +1,95 @@
 #![windows_subsystem = "windows"]
 
-use crate::config::Settings;
+use crate::backend::platform_backend;
+use crate::cli::{Command, ConfigArgs, USAGE};
+use crate::config::{Settings, DEFAULT_CONFIG};
 use crate::desktop::DesktopEventHooks;
 use crate::tray::TrayApp;
 use simple_logger::SimpleLogger;
+use std::env;
+use std::process::ExitCode;
 
+mod backend;
+mod cli;
 mod config;
 mod desktop;
+mod format;
+mod history;
+mod hook;
 mod icon;
+mod keys;
+mod migration;
+mod render;
+mod schema;
+mod sequence;
 mod shortcuts;
+mod theme;
 mod tray;
 mod utils;
+mod validation;
+mod watcher;
 
-fn main() {
+fn main() -> ExitCode {
+    let command = Command::parse(env::args().skip(1));
+    if !matches!(command, Ok(Command::Run { .. })) {
+        cli::attach_console();
+    }
+    let command = guard_clause!(command, error, {
+        eprintln!("Invalid arguments: {:?}\n\n{}", error, USAGE);
+        return ExitCode::from(2);
+    });
+
+    match command {
+        Command::Run { config } => {
+            run(config);
+            ExitCode::SUCCESS
+        }
+        Command::Validate { config, format } => cli::validate(config, format),
+        Command::Convert {
+            config,
+            format,
+            output_path,
+        } => cli::convert(config, format, output_path),
+        Command::Migrate { config, write } => cli::migrate(config, write),
+        Command::PrintDefaultConfig => {
+            print!("{}", DEFAULT_CONFIG);
+            ExitCode::SUCCESS
+        }
+        Command::PrintSchema => cli::print_schema(),
+        Command::Help => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn run(config: ConfigArgs) {
     if let Err(error) = SimpleLogger::new().init() {
         eprintln!("Failed to initialize logger: {}", error);
         return;
     }
 
-    let settings = guard_clause!(Settings::new(), error, {
-        log::error!("Error while reading settings: {:?}", error);
+    let config_location = guard_clause!(config.resolve(), error, {
+        log::error!("Error while locating settings: {}", error);
+        return;
+    });
+
+    let settings = guard_clause!(Settings::load(&config_location), error, {
+        log::error!("Error while reading settings: {}", error);
+        return;
+    });
+
+    let backend = guard_clause!(platform_backend(), error, {
+        log::error!("Error initializing desktop backend: {:?}", error);
         return;
     });
 
-    let desktop_event_hooks = guard_clause!(DesktopEventHooks::new(), error, {
+    let desktop_event_hooks = guard_clause!(DesktopEventHooks::new(backend), error, {
         log::error!("Error initializing desktop event hooks: {:?}", error);
         return;
     });
 
-    if let Err(error) = TrayApp::start(&settings, desktop_event_hooks) {
+    if let Err(error) = TrayApp::start(settings, config_location, desktop_event_hooks) {
         log::error!("Error from TrayApp: {:?}", error)
     }
 }
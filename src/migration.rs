@@ -0,0 +1,342 @@
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter};
+
+/// Settings layout version written by this build.
+pub const CURRENT_VERSION: u32 = 4;
+
+/// Files without a `version` field predate versioning and use the first layout.
+const UNVERSIONED: u32 = 1;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[allow(unused)]
+pub enum MigrationError {
+    NotAMap,
+    InvalidVersion(Value),
+    UnsupportedVersion(u32),
+    /// An old field and its new place hold different settings, so neither can be picked.
+    ConflictingFields {
+        old: String,
+        new: String,
+    },
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::NotAMap => write!(f, "the settings are not a map"),
+            MigrationError::InvalidVersion(version) => write!(f, "invalid version {}", version),
+            MigrationError::UnsupportedVersion(version) => write!(
+                f,
+                "version {} is newer than the supported version {}",
+                version, CURRENT_VERSION
+            ),
+            MigrationError::ConflictingFields { old, new } => write!(
+                f,
+                "`{}` and `{}` are both set but disagree, keep only `{}`",
+                old, new, new
+            ),
+        }
+    }
+}
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), MigrationError>;
+
+/// Migrations indexed by the version they upgrade from, starting at version 1.
+const MIGRATIONS: [Migration; (CURRENT_VERSION - 1) as usize] =
+    [migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+pub fn version_of(settings: &Value) -> Result<u32, MigrationError> {
+    let Some(settings) = settings.as_object() else {
+        return Err(MigrationError::NotAMap);
+    };
+    let Some(version) = settings.get("version") else {
+        return Ok(UNVERSIONED);
+    };
+    // Formats like YAML may hand the number over as a string
+    let parsed_version = match version {
+        Value::Number(number) => number.as_u64(),
+        Value::String(string) => string.parse().ok(),
+        _ => None,
+    };
+    match parsed_version.and_then(|it| u32::try_from(it).ok()) {
+        Some(version) if version >= UNVERSIONED => Ok(version),
+        _ => Err(MigrationError::InvalidVersion(version.clone())),
+    }
+}
+
+/// Upgrades a raw settings tree to the current layout.
+///
+/// Returns the version it started at if anything had to be changed, as files without a `version`
+/// may already use a newer layout.
+pub fn migrate(settings: &mut Value) -> Result<Option<u32>, MigrationError> {
+    let original_version = version_of(settings)?;
+    if original_version > CURRENT_VERSION {
+        return Err(MigrationError::UnsupportedVersion(original_version));
+    }
+    let Some(map) = settings.as_object_mut() else {
+        return Err(MigrationError::NotAMap);
+    };
+
+    let mut original = map.clone();
+    for version in original_version..CURRENT_VERSION {
+        MIGRATIONS[(version - UNVERSIONED) as usize](map)?;
+    }
+    map.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    original.insert("version".to_string(), Value::from(CURRENT_VERSION));
+
+    if *map == original {
+        return Ok(None);
+    }
+    log::info!(
+        "Migrated settings from version {} to {}",
+        original_version,
+        CURRENT_VERSION
+    );
+    Ok(Some(original_version))
+}
+
+/// Moves a field to a new key, leaving nothing behind if the field was absent.
+fn move_field(
+    from: &mut Map<String, Value>,
+    key: &str,
+    to: &mut Map<String, Value>,
+    new_key: &str,
+) {
+    if let Some(value) = from.remove(key) {
+        to.insert(new_key.to_string(), value);
+    }
+}
+
+/// Moves fields into the `group` map, merging them into one the file may already have.
+fn group_fields(
+    settings: &mut Map<String, Value>,
+    group: &str,
+    fields: &[(&str, &str)],
+) -> Result<(), MigrationError> {
+    for &(key, new_key) in fields {
+        let Some(value) = settings.remove(key) else {
+            continue;
+        };
+        let conflict = || MigrationError::ConflictingFields {
+            old: key.to_string(),
+            new: format!("{}.{}", group, new_key),
+        };
+        let Value::Object(grouped) = settings
+            .entry(group)
+            .or_insert_with(|| Value::Object(Map::new()))
+        else {
+            return Err(conflict());
+        };
+        match grouped.get(new_key) {
+            Some(existing) if *existing != value => return Err(conflict()),
+            _ => grouped.insert(new_key.to_string(), value),
+        };
+    }
+    Ok(())
+}
+
+/// Version 2 groups the icon settings under `icons` and the hotkey lists under `hotkeys`.
+///
+/// The lists are grouped first so that version 4 can turn them into a single list of actions in
+/// place, and `icons` is where the later icon options such as `generated` and `themes` live.
+/// Unversioned files may mix in options of later versions, which are kept alongside.
+fn migrate_v1_to_v2(settings: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    group_fields(
+        settings,
+        "icons",
+        &[
+            ("default_icon_path", "default"),
+            ("desktop_index_to_icon_path", "desktops"),
+        ],
+    )?;
+    group_fields(
+        settings,
+        "hotkeys",
+        &[
+            ("switch_desktop_hotkeys", "switch_desktop"),
+            ("move_window_hotkeys", "move_window"),
+        ],
+    )
+}
+
+/// Version 3 replaces `target_desktop_index` with `target_desktop`, which can also pick a desktop by name or GUID.
+fn migrate_v2_to_v3(settings: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    let Some(Value::Object(hotkeys)) = settings.get_mut("hotkeys") else {
+        return Ok(());
+    };
+    for hotkey in hotkeys
+        .values_mut()
+        .filter_map(Value::as_array_mut)
+        .flatten()
+    {
+        let Some(hotkey) = hotkey.as_object_mut() else {
+            continue;
+        };
+        if let Some(index) = hotkey.remove("target_desktop_index") {
+            let target = Map::from_iter([("index".to_string(), index)]);
+            hotkey.insert("target_desktop".to_string(), Value::Object(target));
+        }
+    }
+    Ok(())
+}
+
+/// Version 4 merges the hotkey lists into a single `hotkeys` list, with an `action` on every hotkey.
+fn migrate_v3_to_v4(settings: &mut Map<String, Value>) -> Result<(), MigrationError> {
+    // Unversioned files may already contain the new list
+    let Some(Value::Object(hotkey_lists)) = settings.get_mut("hotkeys") else {
+        return Ok(());
+    };
+    let mut hotkey_lists = std::mem::take(hotkey_lists);
+
+    let mut hotkeys = vec![];
+    for (list_name, action_type) in [("switch_desktop", "switch"), ("move_window", "move-window")] {
+        let Some(Value::Array(list)) = hotkey_lists.remove(list_name) else {
+            continue;
+        };
+        for mut hotkey in list {
+            if let Some(hotkey) = hotkey.as_object_mut() {
+                let mut action = Map::from_iter([("type".to_string(), Value::from(action_type))]);
+                move_field(hotkey, "target_desktop", &mut action, "target");
+                hotkey.insert("action".to_string(), Value::Object(action));
+            }
+            hotkeys.push(hotkey);
+        }
+    }
+    settings.insert("hotkeys".to_string(), Value::Array(hotkeys));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use config::{Config, File, FileFormat};
+
+    /// The same settings in the layout of every version, starting at version 1.
+    const FIXTURES: [&str; CURRENT_VERSION as usize] = [
+        include_str!("../tests/fixtures/migration/v1.yaml"),
+        include_str!("../tests/fixtures/migration/v2.yaml"),
+        include_str!("../tests/fixtures/migration/v3.yaml"),
+        include_str!("../tests/fixtures/migration/v4.yaml"),
+    ];
+
+    /// Reads settings the way settings files are read before they are migrated.
+    fn read(contents: &str) -> Value {
+        Config::builder()
+            .add_source(File::from_str(contents, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn fixture(version: u32) -> Value {
+        read(FIXTURES[(version - UNVERSIONED) as usize])
+    }
+
+    fn without_version(mut settings: Value) -> Map<String, Value> {
+        let mut map = std::mem::take(settings.as_object_mut().unwrap());
+        map.remove("version");
+        map
+    }
+
+    #[test]
+    fn each_step_turns_its_fixture_into_the_next() {
+        for version in UNVERSIONED..CURRENT_VERSION {
+            let mut settings = without_version(fixture(version));
+            MIGRATIONS[(version - UNVERSIONED) as usize](&mut settings).unwrap();
+            assert_eq!(
+                settings,
+                without_version(fixture(version + 1)),
+                "migrating from version {}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn migrates_every_version_to_the_current_layout() {
+        for version in UNVERSIONED..=CURRENT_VERSION {
+            let mut settings = fixture(version);
+            let original_version = migrate(&mut settings).unwrap();
+            assert_eq!(
+                original_version,
+                (version < CURRENT_VERSION).then_some(version)
+            );
+            assert_eq!(
+                settings,
+                fixture(CURRENT_VERSION),
+                "from version {}",
+                version
+            );
+        }
+        let settings: Settings = serde_json::from_value(fixture(CURRENT_VERSION)).unwrap();
+        assert_eq!(settings.hotkeys.len(), 3);
+    }
+
+    #[test]
+    fn merges_old_icon_fields_into_existing_icons() {
+        let mut settings = read(include_str!(
+            "../tests/fixtures/migration/v1-with-icons.yaml"
+        ));
+        assert_eq!(migrate(&mut settings), Ok(Some(UNVERSIONED)));
+        let expected = serde_json::json!({
+            "version": CURRENT_VERSION,
+            "icons": {
+                "default": "C:/icons/default.ico",
+                "desktops": { "0": "C:/icons/desktop1.ico" },
+                "generated": { "label": "number" },
+            },
+        });
+        assert_eq!(settings, expected);
+    }
+
+    #[test]
+    fn rejects_old_fields_that_disagree_with_their_new_place() {
+        let conflict = |old: &str, new: &str| {
+            Err(MigrationError::ConflictingFields {
+                old: old.to_string(),
+                new: new.to_string(),
+            })
+        };
+        let mut settings = serde_json::json!({
+            "default_icon_path": "C:/icons/old.ico",
+            "icons": { "default": "C:/icons/new.ico" },
+        });
+        assert_eq!(
+            migrate(&mut settings),
+            conflict("default_icon_path", "icons.default")
+        );
+        let mut settings = serde_json::json!({
+            "switch_desktop_hotkeys": [],
+            "hotkeys": [{ "modifier_keys": ["Alt"], "trigger_key": "1" }],
+        });
+        assert_eq!(
+            migrate(&mut settings),
+            conflict("switch_desktop_hotkeys", "hotkeys.switch_desktop")
+        );
+        assert_eq!(
+            conflict("default_icon_path", "icons.default")
+                .unwrap_err()
+                .to_string(),
+            "`default_icon_path` and `icons.default` are both set but disagree, keep only \
+             `icons.default`"
+        );
+    }
+
+    #[test]
+    fn rejects_versions_it_cannot_migrate() {
+        let mut settings = serde_json::json!({ "version": CURRENT_VERSION + 1 });
+        assert_eq!(
+            migrate(&mut settings),
+            Err(MigrationError::UnsupportedVersion(CURRENT_VERSION + 1))
+        );
+        let mut settings = serde_json::json!({ "version": 0 });
+        assert_eq!(
+            migrate(&mut settings),
+            Err(MigrationError::InvalidVersion(Value::from(0)))
+        );
+        assert_eq!(migrate(&mut Value::from(4)), Err(MigrationError::NotAMap));
+        assert_eq!(version_of(&serde_json::json!({ "version": "3" })), Ok(3));
+    }
+}
@@ -0,0 +1,653 @@
+use crate::guard_clause;
+use image::imageops::FilterType;
+use image::{ImageFormat, RgbaImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg;
+use std::fmt::{Display, Formatter};
+
+/// Pixel sizes the tray picks from for the different DPI scales, from 100% to 200%.
+pub const ICON_SIZES: [u32; 4] = [16, 20, 24, 32];
+
+/// Samples per pixel along each axis, for smooth edges on shapes and text.
+const SUPERSAMPLING: u32 = 4;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// Glyph width plus the gap to the next glyph, in font units.
+const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+/// An RGBA image with straight alpha, row by row from the top.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+impl Color {
+    /// Parses `#RGB`, `#RRGGBB` or `#RRGGBBAA`.
+    pub fn parse(color: &str) -> Option<Self> {
+        let hex = color.strip_prefix('#')?;
+        if !hex.chars().all(|char| char.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |index: usize, digits: usize| {
+            let value = u8::from_str_radix(&hex[index * digits..(index + 1) * digits], 16).ok()?;
+            // `#RGB` stands for `#RRGGBB`
+            Some(if digits == 1 { value * 17 } else { value })
+        };
+        let (digits, alpha) = match hex.len() {
+            3 => (1, 255),
+            6 => (2, 255),
+            8 => (2, channel(3, 2)?),
+            _ => return None,
+        };
+        Some(Self {
+            red: channel(0, digits)?,
+            green: channel(1, digits)?,
+            blue: channel(2, digits)?,
+            alpha,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Shape {
+    Square,
+    Rounded,
+    Circle,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct LabelStyle {
+    /// Height of the text on a 32 px icon, scaled along with smaller sizes.
+    pub font_size: u32,
+    pub foreground: Color,
+    pub background: Color,
+    pub shape: Shape,
+}
+
+/// Rows of a 5x7 glyph, with the leftmost pixel in the highest of the five bits.
+fn glyph(char: char) -> Option<[u8; GLYPH_HEIGHT]> {
+    let rows = match char.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        _ => return None,
+    };
+    Some(rows)
+}
+
+/// Whether the text can be drawn, as the built-in font only has digits and Latin letters.
+pub fn can_render(char: char) -> bool {
+    glyph(char).is_some()
+}
+
+fn is_inside_shape(shape: Shape, size: f32, x: f32, y: f32) -> bool {
+    let radius = match shape {
+        Shape::Square => return true,
+        Shape::Rounded => size / 4.0,
+        Shape::Circle => size / 2.0,
+    };
+    // Distance to the rectangle left after cutting `radius` off every side
+    let dx = x - x.clamp(radius, size - radius);
+    let dy = y - y.clamp(radius, size - radius);
+    dx * dx + dy * dy <= radius * radius
+}
+
+/// Draws `text` centered on the shape, shrinking it if it would not fit. Characters missing from
+/// the font are skipped.
+pub fn render_label(text: &str, size: u32, style: &LabelStyle) -> Image {
+    let glyphs: Vec<_> = text.chars().filter_map(glyph).collect();
+    let columns = (glyphs.len() * GLYPH_ADVANCE).saturating_sub(1).max(1) as f32;
+
+    let size_f = size as f32;
+    let unit = (style.font_size as f32 / GLYPH_HEIGHT as f32 * size_f / 32.0)
+        .min(size_f * 0.85 / columns)
+        .max(f32::EPSILON);
+    // Whole pixels per font unit and a position on the pixel grid keep the strokes crisp
+    let unit = if unit >= 1.0 { unit.floor() } else { unit };
+    let left = ((size_f - columns * unit) / 2.0).floor();
+    let top = ((size_f - GLYPH_HEIGHT as f32 * unit) / 2.0).floor();
+
+    let is_text = |x: f32, y: f32| {
+        let (column, row) = ((x - left) / unit, (y - top) / unit);
+        if column < 0.0 || row < 0.0 || row >= GLYPH_HEIGHT as f32 {
+            return false;
+        }
+        let (column, row) = (column as usize, row as usize);
+        let (index, glyph_column) = (column / GLYPH_ADVANCE, column % GLYPH_ADVANCE);
+        match glyphs.get(index) {
+            Some(rows) if glyph_column < GLYPH_WIDTH => {
+                rows[row] & (1 << (GLYPH_WIDTH - 1 - glyph_column)) != 0
+            }
+            _ => false,
+        }
+    };
+
+    let mut rgba = Vec::with_capacity((size * size * 4) as usize);
+    let samples = (SUPERSAMPLING * SUPERSAMPLING) as f32;
+    for y in 0..size {
+        for x in 0..size {
+            let (mut shape_hits, mut text_hits) = (0, 0);
+            for sample_y in 0..SUPERSAMPLING {
+                for sample_x in 0..SUPERSAMPLING {
+                    let sample_x = x as f32 + (sample_x as f32 + 0.5) / SUPERSAMPLING as f32;
+                    let sample_y = y as f32 + (sample_y as f32 + 0.5) / SUPERSAMPLING as f32;
+                    if !is_inside_shape(style.shape, size_f, sample_x, sample_y) {
+                        continue;
+                    }
+                    shape_hits += 1;
+                    if is_text(sample_x, sample_y) {
+                        text_hits += 1;
+                    }
+                }
+            }
+            let text_alpha = text_hits as f32 / samples * style.foreground.alpha as f32 / 255.0;
+            let shape_alpha = shape_hits as f32 / samples * style.background.alpha as f32 / 255.0;
+            rgba.extend(blend(
+                style.foreground,
+                text_alpha,
+                style.background,
+                shape_alpha,
+            ));
+        }
+    }
+    Image {
+        width: size,
+        height: size,
+        rgba,
+    }
+}
+
+/// Composites the text color with coverage `top_alpha` over the background with `bottom_alpha`.
+fn blend(top: Color, top_alpha: f32, bottom: Color, bottom_alpha: f32) -> [u8; 4] {
+    let alpha = top_alpha + bottom_alpha * (1.0 - top_alpha);
+    if alpha <= 0.0 {
+        return [0, 0, 0, 0];
+    }
+    let channel = |top: u8, bottom: u8| {
+        let value =
+            (top as f32 * top_alpha + bottom as f32 * bottom_alpha * (1.0 - top_alpha)) / alpha;
+        value.round() as u8
+    };
+    [
+        channel(top.red, bottom.red),
+        channel(top.green, bottom.green),
+        channel(top.blue, bottom.blue),
+        (alpha * 255.0).round() as u8,
+    ]
+}
+
+/// Packs the images into an ICO file, each as an uncompressed 32-bit bitmap.
+pub fn encode_ico(images: &[Image]) -> Vec<u8> {
+    const HEADER_SIZE: usize = 6;
+    const ENTRY_SIZE: usize = 16;
+    const BITMAP_HEADER_SIZE: usize = 40;
+
+    let mut directory = Vec::with_capacity(HEADER_SIZE + images.len() * ENTRY_SIZE);
+    directory.extend(0u16.to_le_bytes());
+    directory.extend(1u16.to_le_bytes());
+    directory.extend((images.len() as u16).to_le_bytes());
+
+    let mut data = vec![];
+    let data_offset = HEADER_SIZE + images.len() * ENTRY_SIZE;
+    for image in images {
+        // The AND mask is left empty, as the alpha channel takes its place
+        let mask_row_size = image.width.div_ceil(32) as usize * 4;
+        let mask_size = mask_row_size * image.height as usize;
+        let image_size = BITMAP_HEADER_SIZE + image.rgba.len() + mask_size;
+
+        // Sizes of 256 px are written as 0
+        directory.push(image.width as u8);
+        directory.push(image.height as u8);
+        directory.extend([0, 0]);
+        directory.extend(1u16.to_le_bytes());
+        directory.extend(32u16.to_le_bytes());
+        directory.extend((image_size as u32).to_le_bytes());
+        directory.extend(((data_offset + data.len()) as u32).to_le_bytes());
+
+        data.extend((BITMAP_HEADER_SIZE as u32).to_le_bytes());
+        data.extend((image.width as i32).to_le_bytes());
+        // Bitmaps in icons count the mask into their height
+        data.extend((image.height as i32 * 2).to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(32u16.to_le_bytes());
+        data.extend([0; 4 * 6]);
+
+        // Rows go from the bottom up, with the channels in BGRA order
+        for row in image.rgba.chunks(image.width as usize * 4).rev() {
+            for pixel in row.chunks(4) {
+                data.extend([pixel[2], pixel[1], pixel[0], pixel[3]]);
+            }
+        }
+        data.extend(std::iter::repeat_n(0, mask_size));
+    }
+
+    directory.extend(data);
+    directory
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    UnknownFormat,
+    DecodingFailed(image::ImageError),
+    SvgParsingFailed(usvg::Error),
+    /// The SVG has no size to scale from, or the pixels couldn't be allocated.
+    SvgRenderingFailed,
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::UnknownFormat => write!(f, "not an ICO, PNG, BMP or SVG file"),
+            ImageError::DecodingFailed(error) => write!(f, "{}", error),
+            ImageError::SvgParsingFailed(error) => write!(f, "{}", error),
+            ImageError::SvgRenderingFailed => write!(f, "SVG could not be rendered"),
+        }
+    }
+}
+
+fn is_svg(buffer: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(buffer) else {
+        return false;
+    };
+    let text = text.trim_start_matches('\u{FEFF}').trim_start();
+    text.starts_with('<') && text.contains("<svg")
+}
+
+/// Turns the contents of an icon file into an ICO file. ICO files are kept as they are, while PNG,
+/// BMP and SVG images are scaled to every size in `ICON_SIZES`.
+pub fn to_ico(buffer: Vec<u8>) -> Result<Vec<u8>, ImageError> {
+    let images = if buffer.starts_with(&[0, 0, 1, 0]) {
+        return Ok(buffer);
+    } else if buffer.starts_with(b"\x89PNG") {
+        decode_raster(&buffer, ImageFormat::Png)?
+    } else if buffer.starts_with(b"BM") {
+        decode_raster(&buffer, ImageFormat::Bmp)?
+    } else if is_svg(&buffer) {
+        rasterize_svg(&buffer)?
+    } else {
+        return Err(ImageError::UnknownFormat);
+    };
+    Ok(encode_ico(&images))
+}
+
+/// Scales the image into squares of every icon size, centering it if it isn't square itself.
+fn decode_raster(buffer: &[u8], format: ImageFormat) -> Result<Vec<Image>, ImageError> {
+    let decoded = guard_clause!(
+        image::load_from_memory_with_format(buffer, format),
+        error,
+        {
+            return Err(ImageError::DecodingFailed(error));
+        }
+    );
+    let decoded = decoded.into_rgba8();
+
+    let images = ICON_SIZES.iter().map(|size| {
+        let scale = *size as f32 / decoded.width().max(decoded.height()) as f32;
+        let width = ((decoded.width() as f32 * scale).round() as u32).max(1);
+        let height = ((decoded.height() as f32 * scale).round() as u32).max(1);
+        let resized = image::imageops::resize(&decoded, width, height, FilterType::Lanczos3);
+
+        let mut square = RgbaImage::new(*size, *size);
+        let left = (*size - width) / 2;
+        let top = (*size - height) / 2;
+        image::imageops::overlay(&mut square, &resized, left as i64, top as i64);
+        Image {
+            width: *size,
+            height: *size,
+            rgba: square.into_raw(),
+        }
+    });
+    Ok(images.collect())
+}
+
+fn rasterize_svg(buffer: &[u8]) -> Result<Vec<Image>, ImageError> {
+    let tree = guard_clause!(
+        usvg::Tree::from_data(buffer, &usvg::Options::default()),
+        error,
+        {
+            return Err(ImageError::SvgParsingFailed(error));
+        }
+    );
+    let svg_size = tree.size();
+
+    let mut images = vec![];
+    for size in ICON_SIZES {
+        let mut pixmap = Pixmap::new(size, size).ok_or(ImageError::SvgRenderingFailed)?;
+        let scale = size as f32 / svg_size.width().max(svg_size.height());
+        let transform = Transform::from_scale(scale, scale).post_translate(
+            (size as f32 - svg_size.width() * scale) / 2.0,
+            (size as f32 - svg_size.height() * scale) / 2.0,
+        );
+        resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+        // The pixmap holds premultiplied colors
+        let rgba = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let pixel = pixel.demultiply();
+                [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+            })
+            .collect();
+        images.push(Image {
+            width: size,
+            height: size,
+            rgba,
+        });
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: LabelStyle = LabelStyle {
+        font_size: 20,
+        foreground: Color {
+            red: 255,
+            green: 255,
+            blue: 255,
+            alpha: 255,
+        },
+        background: Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 255,
+        },
+        shape: Shape::Rounded,
+    };
+
+    /// One character per pixel: outside the shape, background, partly covered and text.
+    fn draw(image: &Image) -> Vec<String> {
+        image
+            .rgba
+            .chunks(image.width as usize * 4)
+            .map(|row| {
+                row.chunks(4)
+                    .map(|pixel| match (pixel[3], pixel[0]) {
+                        (0, _) => ' ',
+                        (_, 0..=63) => '.',
+                        (_, 64..=191) => '+',
+                        _ => '#',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// FNV-1a, which unlike `DefaultHasher` gives the same hash on every Rust version.
+    fn hash(image: &Image) -> u64 {
+        image.rgba.iter().fold(0xCBF29CE484222325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001B3)
+        })
+    }
+
+    #[test]
+    fn draws_digits_on_the_pixel_grid() {
+        assert_eq!(
+            draw(&render_label("1", 16, &STYLE)),
+            [
+                " .............. ",
+                "................",
+                "................",
+                "................",
+                ".......#........",
+                "......##........",
+                ".......#........",
+                ".......#........",
+                ".......#........",
+                ".......#........",
+                "......###.......",
+                "................",
+                "................",
+                "................",
+                "................",
+                " .............. ",
+            ]
+        );
+        assert_eq!(
+            draw(&render_label("12", 16, &STYLE)),
+            [
+                " .............. ",
+                "................",
+                "................",
+                "................",
+                "....#....###....",
+                "...##...#...#...",
+                "....#.......#...",
+                "....#......#....",
+                "....#.....#.....",
+                "....#....#......",
+                "...###..#####...",
+                "................",
+                "................",
+                "................",
+                "................",
+                " .............. ",
+            ]
+        );
+    }
+
+    #[test]
+    fn shrinks_long_text_to_fit() {
+        assert_eq!(
+            draw(&render_label("ABCDEFGHIJ", 32, &STYLE)),
+            [
+                "    ........................    ",
+                "  ............................  ",
+                " .............................. ",
+                " .............................. ",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "..++++++++++.+++++++++.+++..+...",
+                "..++++++..+.+++++.+.++++.+..+...",
+                "..++++++++++.++++.++++.+.+.++...",
+                ".....+..+....++....++....+......",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                "................................",
+                " .............................. ",
+                " .............................. ",
+                "  ............................  ",
+                "    ........................    ",
+            ]
+        );
+        // The text takes up at most 85% of the width, centered
+        for size in ICON_SIZES {
+            let image = render_label("ABCDEFGHIJ", size, &STYLE);
+            let margin = (size as f32 * 0.075).floor() as usize;
+            for row in draw(&image) {
+                let text_columns: Vec<_> = row.match_indices(['+', '#']).map(|(x, _)| x).collect();
+                assert!(
+                    text_columns
+                        .iter()
+                        .all(|x| (margin..size as usize - margin).contains(x)),
+                    "{} px: {:?}",
+                    size,
+                    row
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn renders_the_same_pixels_at_every_size() {
+        let expected = [
+            ("1", 16, 0xFA12_9313_06E9_F26D),
+            ("1", 20, 0x3167_FD54_D15E_E5ED),
+            ("1", 24, 0x0411_E4D5_2777_6345),
+            ("1", 32, 0x38CE_F160_D71E_6005),
+            ("12", 16, 0xD994_0E53_D708_107D),
+            ("12", 20, 0x91AD_402C_D3A3_92FD),
+            ("12", 24, 0x104A_400E_D4BF_621D),
+            ("12", 32, 0xD4C0_1F0E_4750_C925),
+            ("ABCDEFGHIJ", 16, 0x5B7C_205E_7FD3_828C),
+            ("ABCDEFGHIJ", 20, 0x141E_2568_8816_B9EC),
+            ("ABCDEFGHIJ", 24, 0xAD9D_EAF2_F572_C59D),
+            ("ABCDEFGHIJ", 32, 0x4747_F0D0_F0A5_FA3C),
+        ];
+        for (text, size, expected_hash) in expected {
+            let image = render_label(text, size, &STYLE);
+            assert_eq!((image.width, image.height), (size, size));
+            assert_eq!(hash(&image), expected_hash, "'{}' at {} px", text, size);
+        }
+    }
+
+    /// Reads the size of every image in an ICO file, from its directory and from its bitmap.
+    fn ico_sizes(buffer: &[u8]) -> Vec<(u32, u32)> {
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap());
+        assert_eq!(buffer[..4], [0, 0, 1, 0]);
+        let count = u16::from_le_bytes([buffer[4], buffer[5]]) as usize;
+        (0..count)
+            .map(|index| {
+                let entry = 6 + index * 16;
+                let (width, height) = (buffer[entry] as u32, buffer[entry + 1] as u32);
+                let bitmap = read_u32(entry + 12) as usize;
+                assert_eq!(read_u32(bitmap + 4), width);
+                assert_eq!(read_u32(bitmap + 8), height * 2);
+                (width, height)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converts_images_to_every_icon_size() {
+        let fixtures: [(&str, &[u8]); 5] = [
+            (
+                "square.png",
+                include_bytes!("../tests/fixtures/icons/square.png"),
+            ),
+            (
+                "wide.png",
+                include_bytes!("../tests/fixtures/icons/wide.png"),
+            ),
+            (
+                "square.bmp",
+                include_bytes!("../tests/fixtures/icons/square.bmp"),
+            ),
+            (
+                "square.svg",
+                include_bytes!("../tests/fixtures/icons/square.svg"),
+            ),
+            (
+                "wide.svg",
+                include_bytes!("../tests/fixtures/icons/wide.svg"),
+            ),
+        ];
+        for (name, buffer) in fixtures {
+            let ico = to_ico(buffer.to_vec()).unwrap_or_else(|error| panic!("{}: {}", name, error));
+            let sizes: Vec<_> = ICON_SIZES.iter().map(|size| (*size, *size)).collect();
+            assert_eq!(ico_sizes(&ico), sizes, "{}", name);
+            // Already an ICO file, so it's kept as it is
+            assert_eq!(to_ico(ico.clone()).unwrap(), ico, "{}", name);
+        }
+    }
+
+    #[test]
+    fn centers_images_that_are_not_square() {
+        let png = include_bytes!("../tests/fixtures/icons/wide.png");
+        let svg = include_bytes!("../tests/fixtures/icons/wide.svg");
+        let images = [
+            ("wide.png", decode_raster(png, ImageFormat::Png).unwrap()),
+            ("wide.svg", rasterize_svg(svg).unwrap()),
+        ];
+        for (name, images) in images {
+            for image in images {
+                let alpha = |x: u32, y: u32| image.rgba[((y * image.width + x) * 4 + 3) as usize];
+                let (middle, size) = (image.height / 2, image.height);
+                // Twice as wide as high, leaving a quarter of the height empty above and below
+                assert_eq!(alpha(0, 0), 0, "{} at {} px", name, size);
+                assert_eq!(alpha(0, size - 1), 0, "{} at {} px", name, size);
+                assert_eq!(alpha(0, middle), 255, "{} at {} px", name, size);
+                assert_eq!(alpha(size - 1, middle), 255, "{} at {} px", name, size);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_images_it_cannot_read() {
+        let truncated = include_bytes!("../tests/fixtures/icons/truncated.png");
+        let broken = include_bytes!("../tests/fixtures/icons/broken.svg");
+        let text = include_bytes!("../tests/fixtures/icons/not-an-image.txt");
+
+        assert!(matches!(
+            to_ico(truncated.to_vec()),
+            Err(ImageError::DecodingFailed(_))
+        ));
+        assert!(matches!(
+            to_ico(b"BM not really a bitmap".to_vec()),
+            Err(ImageError::DecodingFailed(_))
+        ));
+        assert!(matches!(
+            to_ico(broken.to_vec()),
+            Err(ImageError::SvgParsingFailed(_))
+        ));
+        assert!(matches!(
+            to_ico(text.to_vec()),
+            Err(ImageError::UnknownFormat)
+        ));
+        assert!(matches!(to_ico(vec![]), Err(ImageError::UnknownFormat)));
+    }
+}
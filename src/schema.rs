@@ -0,0 +1,151 @@
+use crate::config::Settings;
+use crate::keys::key_names;
+use schemars::{json_schema, schema_for, Schema, SchemaGenerator};
+
+/// JSON Schema of the settings file, for editor completion and validation.
+pub fn settings_schema() -> Schema {
+    schema_for!(Settings)
+}
+
+/// Lists the key names for completion, while accepting every spelling `find_key` does, as far as a
+/// pattern can tell.
+pub fn key_name_schema(_generator: &mut SchemaGenerator) -> Schema {
+    let names: Vec<&str> = key_names().collect();
+    json_schema!({
+        "type": "string",
+        "anyOf": [
+            { "enum": names },
+            { "pattern": key_name_pattern() },
+        ],
+    })
+}
+
+/// Accepts the `+`-separated key names of `HotKey::keys`, which may end in a digit range like `1-9`.
+pub fn keys_schema(_generator: &mut SchemaGenerator) -> Schema {
+    let name = key_name_regex();
+    json_schema!({
+        "type": "string",
+        "pattern": format!(r"^\s*(?:{name}\s*\+\s*)*(?:{name}|[1-9]\s*-\s*[1-9])\s*$"),
+    })
+}
+
+fn key_name_pattern() -> String {
+    format!("^{}$", key_name_regex())
+}
+
+/// JSON Schema patterns have no flag to ignore case, so every letter becomes a class like `[aA]`.
+fn key_name_regex() -> String {
+    let alternatives: Vec<String> = key_names()
+        .map(|name| {
+            let chars: Vec<String> = name
+                .chars()
+                .map(|char| match char.is_ascii_alphabetic() {
+                    true => format!(
+                        "[{}{}]",
+                        char.to_ascii_lowercase(),
+                        char.to_ascii_uppercase()
+                    ),
+                    false => char.to_string(),
+                })
+                .collect();
+            chars.join("_?")
+        })
+        .collect();
+    format!(
+        "(?:(?:[vV][kK]_)?(?:{})|0[xX][0-9a-fA-F]{{1,4}})",
+        alternatives.join("|")
+    )
+}
+
+pub fn key_names_schema(generator: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "type": "array",
+        "items": key_name_schema(generator),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use serde_json::{json, Value};
+
+    fn validator() -> jsonschema::Validator {
+        let schema = serde_json::to_value(settings_schema()).unwrap();
+        jsonschema::validator_for(&schema).unwrap()
+    }
+
+    fn hotkey(modifier_key: &str, trigger_key: &str) -> Value {
+        json!({ "hotkeys": [{
+            "modifier_keys": [modifier_key],
+            "trigger_key": trigger_key,
+            "action": { "type": "switch", "target": { "index": 0 } },
+        }] })
+    }
+
+    #[test]
+    fn accepts_the_readme_example() {
+        let readme = include_str!("../README.md");
+        let example = readme
+            .split("### Example configuration")
+            .nth(1)
+            .and_then(|section| section.split("```yaml").nth(1))
+            .and_then(|block| block.split("```").next())
+            .expect("README should have an example configuration");
+        // Loaded like the settings files are, which turns the desktop indices into strings
+        let example: Value = Config::builder()
+            .add_source(File::from_str(example, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        let validator = validator();
+        let errors: Vec<_> = validator
+            .iter_errors(&example)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn rejects_bad_hotkeys() {
+        let validator = validator();
+        assert!(!validator.is_valid(&hotkey("Alt", "Bogus")));
+        assert!(!validator.is_valid(&hotkey("Alt+", "1")));
+        let mut bad_action = hotkey("Alt", "1");
+        bad_action["hotkeys"][0]["action"] = json!({ "type": "teleport" });
+        assert!(!validator.is_valid(&bad_action));
+    }
+
+    #[test]
+    fn checks_the_key_names_of_compact_keys() {
+        let validator = validator();
+        let hotkey = |keys: &str| {
+            json!({ "hotkeys": [{
+                "keys": keys,
+                "action": { "type": "switch", "target": { "index": 0 } },
+            }] })
+        };
+        for keys in ["Alt+1", " ctrl + ALT + right ", "Win+Shift+1-9", "vk_oem_plus", "0x41"] {
+            assert!(validator.is_valid(&hotkey(keys)), "{}", keys);
+        }
+        for keys in ["Alt+Bogus", "Alt+", "+1", "Alt+0-9", "Alt+1-9+D", ""] {
+            assert!(!validator.is_valid(&hotkey(keys)), "{}", keys);
+        }
+    }
+
+    #[test]
+    fn accepts_key_names_in_any_spelling() {
+        let validator = validator();
+        for (modifier_key, trigger_key) in [
+            ("Alt", "1"),
+            ("alt", "right"),
+            ("CTRL", "VK_OEM_PLUS"),
+            ("vk_lwin", "0x41"),
+        ] {
+            let hotkey = hotkey(modifier_key, trigger_key);
+            assert!(validator.is_valid(&hotkey), "{}", hotkey);
+        }
+    }
+}
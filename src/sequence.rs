@@ -0,0 +1,203 @@
+use std::time::{Duration, Instant};
+
+/// Outcome of feeding a key press to a `SequenceMatcher`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SequenceStep<A> {
+    /// A whole sequence was pressed, and the matcher is back at its start.
+    Matched(A),
+    /// The keys so far start at least one sequence, so more are expected before the deadline.
+    Pending,
+    /// The key doesn't continue any sequence, and the matcher is back at its start.
+    NoMatch,
+}
+
+/// Matches key sequences such as `Alt+D, 3`, where every key press `K` can be a whole chord.
+///
+/// Time is passed in rather than read, which keeps the matcher independent of the hotkey thread.
+#[derive(Clone, Debug)]
+pub struct SequenceMatcher<K, A> {
+    sequences: Vec<(Vec<K>, A)>,
+    timeout: Duration,
+    pressed: Vec<K>,
+    deadline: Option<Instant>,
+}
+
+impl<K: Clone + Eq, A: Clone> SequenceMatcher<K, A> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            sequences: vec![],
+            timeout,
+            pressed: vec![],
+            deadline: None,
+        }
+    }
+
+    pub fn add(&mut self, keys: Vec<K>, action: A) {
+        self.sequences.push((keys, action));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pressed.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.pressed.clear();
+        self.deadline = None;
+    }
+
+    /// The keys that continue the sequences starting with `pressed`, without duplicates.
+    fn keys_after(&self, pressed: &[K]) -> Vec<K> {
+        let mut keys: Vec<K> = vec![];
+        for (sequence, _) in &self.sequences {
+            if sequence.len() <= pressed.len() || !sequence.starts_with(pressed) {
+                continue;
+            }
+            let key = &sequence[pressed.len()];
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+        keys
+    }
+
+    /// The keys that can be pressed next, without duplicates: the first key of every sequence, and
+    /// the keys that continue the pending sequences.
+    pub fn expected_keys(&self) -> Vec<K> {
+        let mut keys = self.keys_after(&self.pressed);
+        for key in self.keys_after(&[]) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// Abandons a pending sequence once its deadline has passed, returning whether it did.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                self.reset();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn press(&mut self, key: K, now: Instant) -> SequenceStep<A> {
+        self.expire(now);
+        // A key that doesn't continue the pending sequences abandons them and starts over, so that
+        // other hotkeys keep working in the meantime
+        if self.is_pending() && !self.keys_after(&self.pressed).contains(&key) {
+            self.reset();
+        }
+        self.pressed.push(key);
+
+        // A sequence that is also the start of a longer one wins, validation reports the conflict
+        if let Some((_, action)) = self
+            .sequences
+            .iter()
+            .find(|(sequence, _)| *sequence == self.pressed)
+        {
+            let action = action.clone();
+            self.reset();
+            return SequenceStep::Matched(action);
+        }
+        if self.keys_after(&self.pressed).is_empty() {
+            self.reset();
+            return SequenceStep::NoMatch;
+        }
+        self.deadline = Some(now + self.timeout);
+        SequenceStep::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(1500);
+
+    fn matcher() -> SequenceMatcher<&'static str, u32> {
+        let mut matcher = SequenceMatcher::new(TIMEOUT);
+        matcher.add(vec!["Alt+D", "1"], 1);
+        matcher.add(vec!["Alt+D", "2", "3"], 23);
+        matcher.add(vec!["Alt+1"], 100);
+        matcher
+    }
+
+    #[test]
+    fn prefix_is_pending_until_the_deadline() {
+        let mut matcher = matcher();
+        let now = Instant::now();
+
+        assert_eq!(matcher.press("Alt+D", now), SequenceStep::Pending);
+        assert!(matcher.is_pending());
+        assert!(!matcher.expire(now + TIMEOUT - Duration::from_millis(1)));
+        assert_eq!(matcher.expected_keys(), vec!["1", "2", "Alt+D", "Alt+1"]);
+    }
+
+    #[test]
+    fn full_sequence_matches_and_resets() {
+        let mut matcher = matcher();
+        let now = Instant::now();
+
+        assert_eq!(matcher.press("Alt+D", now), SequenceStep::Pending);
+        assert_eq!(matcher.press("2", now), SequenceStep::Pending);
+        assert_eq!(matcher.press("3", now), SequenceStep::Matched(23));
+        assert!(!matcher.is_pending());
+        assert_eq!(matcher.press("Alt+1", now), SequenceStep::Matched(100));
+    }
+
+    #[test]
+    fn timeout_abandons_the_pending_sequence() {
+        let mut matcher = matcher();
+        let now = Instant::now();
+
+        matcher.press("Alt+D", now);
+        assert!(!matcher.expire(now + TIMEOUT / 2));
+        assert!(matcher.expire(now + TIMEOUT));
+        assert!(!matcher.is_pending());
+        assert_eq!(matcher.expected_keys(), vec!["Alt+D", "Alt+1"]);
+
+        // Pressing the continuation after the deadline doesn't complete the sequence either
+        matcher.press("Alt+D", now);
+        assert_eq!(matcher.press("1", now + TIMEOUT), SequenceStep::NoMatch);
+    }
+
+    #[test]
+    fn mismatch_resets() {
+        let mut matcher = matcher();
+        let now = Instant::now();
+
+        assert_eq!(matcher.press("3", now), SequenceStep::NoMatch);
+        matcher.press("Alt+D", now);
+        assert_eq!(matcher.press("3", now), SequenceStep::NoMatch);
+        assert!(!matcher.is_pending());
+        assert_eq!(matcher.press("1", now), SequenceStep::NoMatch);
+    }
+
+    #[test]
+    fn other_hotkey_cancels_the_pending_sequence() {
+        let mut matcher = matcher();
+        let now = Instant::now();
+
+        matcher.press("Alt+D", now);
+        assert_eq!(matcher.press("Alt+1", now), SequenceStep::Matched(100));
+        assert!(!matcher.is_pending());
+
+        matcher.press("Alt+D", now);
+        assert_eq!(matcher.press("Alt+D", now), SequenceStep::Pending);
+        assert_eq!(matcher.press("1", now), SequenceStep::Matched(1));
+    }
+
+    #[test]
+    fn shorter_sequence_wins_over_a_longer_one() {
+        let mut matcher = matcher();
+        matcher.add(vec!["Alt+D", "2"], 2);
+        let now = Instant::now();
+
+        matcher.press("Alt+D", now);
+        assert_eq!(matcher.press("2", now), SequenceStep::Matched(2));
+        assert_eq!(matcher.press("3", now), SequenceStep::NoMatch);
+    }
+}
@@ -1,184 +1,676 @@
-use crate::config::{HotKey, Settings};
-use crate::desktop::DesktopEventHooks;
+use crate::backend::VirtualDesktopBackend;
+use crate::config::{Action, Binding, DesktopTarget, Settings};
+use crate::desktop::{normalize_guid, DesktopEventHooks, DesktopInfo};
 use crate::guard_clause;
-use std::ffi::c_void;
-use std::str::FromStr;
-use std::thread;
-use win_hotkeys::error::WHKError;
-use win_hotkeys::{HotkeyManager, InterruptHandle, VKey};
-use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::SetForegroundWindow;
-use winvd::{get_desktops, is_window_on_current_desktop, move_window_to_desktop, switch_desktop};
-use x_win::{get_active_window, get_open_windows, WindowInfo};
+use crate::history::{DesktopHistory, SharedHistory};
+use crate::keys::{KeyCombo, KeyError};
+use crate::sequence::{SequenceMatcher, SequenceStep};
+use crate::hook::{check_available, set_key_handler, KeyHandler, RegistrationError};
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, Instant};
+use std::{process, thread};
 
 pub struct ShortcutHandler {
-    interrupt_handle: InterruptHandle,
+    failures: Vec<RegistrationFailure>,
+}
+
+/// Actions that only the tray app can carry out, as it owns the settings and the event loop.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AppAction {
+    OpenTaskView,
+    ReloadConfig,
+}
+
+/// Everything a hotkey needs to carry out its action, cloned into every hotkey callback.
+#[derive(Clone)]
+pub struct ActionContext<B: VirtualDesktopBackend> {
+    pub backend: B,
+    pub history: SharedHistory,
+    pub app_actions: Sender<AppAction>,
 }
 
 #[derive(Debug)]
 #[allow(unused)]
 pub enum ShortcutError {
-    InvalidKey(WHKError),
-    HotKeyRegistrationFailed(WHKError),
-}
-
-impl HotKey {
-    fn parse(&self) -> Result<(VKey, Vec<VKey>, u32), WHKError> {
-        let HotKey {
-            modifier_keys,
-            trigger_key,
-            target_desktop_index,
-        } = self;
-        let trigger_key = guard_clause!(VKey::from_str(&trigger_key), error, {
-            return Err(error);
-        });
+    InvalidKey(KeyError),
+}
+
+/// A key combination that couldn't be registered, e.g. because another program already uses it.
+#[derive(Debug)]
+pub struct RegistrationFailure {
+    pub keys: KeyCombo,
+    pub error: RegistrationError,
+}
 
-        let modifier_keys: Result<Vec<_>, _> =
-            modifier_keys.iter().map(|it| VKey::from_str(&it)).collect();
+impl Display for RegistrationFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.keys, self.error)
+    }
+}
 
-        let modifier_keys = guard_clause!(modifier_keys, error, {
-            return Err(error);
-        });
+impl DesktopTarget {
+    fn find_index(
+        &self,
+        desktops: &[DesktopInfo],
+        current_index: u32,
+        history: &DesktopHistory,
+    ) -> Option<u32> {
+        let last_index = (desktops.len() as u32).checked_sub(1)?;
+        let index = match self {
+            DesktopTarget::Current => Some(current_index),
+            DesktopTarget::Index(index) => desktops
+                .iter()
+                .any(|desktop| desktop.index == *index)
+                .then_some(*index),
+            DesktopTarget::Name(name) => desktops
+                .iter()
+                .find(|desktop| &desktop.name == name)
+                .map(|desktop| desktop.index),
+            DesktopTarget::Guid(guid) => {
+                let guid = normalize_guid(guid);
+                desktops
+                    .iter()
+                    .find(|desktop| {
+                        desktop
+                            .guid
+                            .as_deref()
+                            .is_some_and(|it| normalize_guid(it) == guid)
+                    })
+                    .map(|desktop| desktop.index)
+            }
+            DesktopTarget::Next { wrap } => {
+                if current_index < last_index {
+                    Some(current_index + 1)
+                } else {
+                    wrap.then_some(0)
+                }
+            }
+            DesktopTarget::Previous { wrap } => {
+                if current_index > 0 {
+                    Some(current_index - 1)
+                } else {
+                    wrap.then_some(last_index)
+                }
+            }
+            DesktopTarget::First => Some(0),
+            DesktopTarget::Last => Some(last_index),
+            DesktopTarget::LastUsed => history.last_used(desktops),
+            DesktopTarget::Back => history.back_target(desktops),
+            DesktopTarget::Forward => history.forward_target(desktops),
+        };
+        index.filter(|index| *index <= last_index)
+    }
+}
 
-        Ok((trigger_key, modifier_keys, *target_desktop_index))
+/// Looks up the current index of `target`, logging an error if no such desktop exists right now.
+pub fn resolve_target<B: VirtualDesktopBackend>(
+    backend: &B,
+    history: &DesktopHistory,
+    target: &DesktopTarget,
+) -> Option<u32> {
+    let current = guard_clause!(backend.get_current_desktop(), error, {
+        log::error!(
+            "Failed to get current desktop to find {}: {:?}",
+            target,
+            error
+        );
+        return None;
+    });
+    resolve_target_from(backend, history, target, current.index)
+}
+
+/// Like `resolve_target`, but with `from_index` standing in for the current desktop.
+pub fn resolve_target_from<B: VirtualDesktopBackend>(
+    backend: &B,
+    history: &DesktopHistory,
+    target: &DesktopTarget,
+    from_index: u32,
+) -> Option<u32> {
+    let desktops = guard_clause!(backend.get_desktops(), error, {
+        log::error!("Failed to get desktops to find {}: {:?}", target, error);
+        return None;
+    });
+
+    let index = target.find_index(&desktops, from_index, history);
+    if index.is_none() && target.is_relative() {
+        // Reaching the end without wrap-around is expected, not an error
+        log::info!("There is no {} from desktop {}", target, from_index);
+    } else if index.is_none() {
+        let names: Vec<_> = desktops
+            .iter()
+            .map(|desktop| desktop.name.as_str())
+            .collect();
+        log::error!(
+            "There is no {}, the current desktops are {:?}",
+            target,
+            names
+        );
     }
+    index
 }
 
-impl ShortcutHandler {
-    pub fn new(
-        settings: &Settings,
-        desktop_event_hooks: DesktopEventHooks,
-    ) -> Result<Self, ShortcutError> {
-        let mut hkm = HotkeyManager::new();
+/// Returns whether the switch succeeded.
+pub fn switch_to_desktop<B: VirtualDesktopBackend>(backend: &B, target_index: u32) -> bool {
+    if let Err(error) = backend.switch_desktop(target_index) {
+        log::error!("Failed to switch to desktop {}: {:?}", target_index, error);
+        return false;
+    }
+    true
+}
 
-        let handler = Self {
-            interrupt_handle: hkm.interrupt_handle(),
-        };
+/// Returns whether it switched to the desktop along with the window.
+pub fn move_window_to_desktop<B: VirtualDesktopBackend>(
+    backend: &B,
+    target_index: u32,
+    follow_moved_windows: bool,
+) -> bool {
+    if let Err(error) = backend.move_active_window_to_desktop(target_index) {
+        log::error!(
+            "Failed to move window to desktop {}: {:?}",
+            target_index,
+            error
+        );
+        return false;
+    }
+    follow_moved_windows && switch_to_desktop(backend, target_index)
+}
 
-        for hotkey in &settings.switch_desktop_hotkeys {
-            let (trigger_key, modifier_keys, desktop_index) =
-                guard_clause!(hotkey.parse(), error, {
-                    return Err(ShortcutError::InvalidKey(error));
-                });
-
-            let target_index = desktop_index.clone() as usize;
-            let switch_lambda = move || {
-                let current_desktops = guard_clause!(get_desktops(), error, {
-                    log::error!("Failed to get desktops: {:?}", error);
-                    return;
-                });
-
-                match current_desktops.get(target_index) {
-                    None => log::error!(
-                        "Desktop index not found while attempting to switch: {}",
-                        target_index,
-                    ),
-                    Some(&target_desktop) => {
-                        if let Err(error) = switch_desktop(target_desktop) {
-                            log::error!("Failed to switch desktop: {:?}", error);
-                        }
-                    }
+pub fn rename_desktop<B: VirtualDesktopBackend>(backend: &B, target_index: u32, name: &str) {
+    if let Err(error) = backend.rename_desktop(target_index, name) {
+        log::error!(
+            "Failed to rename desktop {} to '{}': {:?}",
+            target_index,
+            name,
+            error
+        );
+    }
+}
+
+pub fn create_desktop<B: VirtualDesktopBackend>(backend: &B, name: Option<&str>, switch: bool) {
+    let index = guard_clause!(backend.create_desktop(), error, {
+        log::error!("Failed to create desktop: {:?}", error);
+        return;
+    });
+    if let Some(name) = name {
+        rename_desktop(backend, index, name);
+    }
+    if switch {
+        switch_to_desktop(backend, index);
+    }
+}
+
+pub fn remove_desktop<B: VirtualDesktopBackend>(
+    backend: &B,
+    target_index: u32,
+    fallback_index: u32,
+) {
+    // Also the case when removing the only desktop, as every target resolves to it
+    if target_index == fallback_index {
+        log::error!(
+            "Not removing desktop {}, as it is its own fallback",
+            target_index
+        );
+        return;
+    }
+    if let Err(error) = backend.remove_desktop(target_index, fallback_index) {
+        log::error!("Failed to remove desktop {}: {:?}", target_index, error);
+    }
+}
+
+fn run_command(command: &str, args: &[String]) {
+    if let Err(error) = process::Command::new(command).args(args).spawn() {
+        log::error!("Failed to run '{}': {}", command, error);
+    }
+}
+
+fn send_app_action<B: VirtualDesktopBackend>(context: &ActionContext<B>, action: AppAction) {
+    if context.app_actions.send(action).is_err() {
+        log::error!("Could not forward {:?}, the tray app has stopped", action);
+    }
+}
+
+/// Takes the step through the history that switching to `target` stands for.
+fn record_switch(history: &mut DesktopHistory, target: &DesktopTarget) {
+    match target {
+        DesktopTarget::Back => history.go_back(),
+        DesktopTarget::Forward => history.go_forward(),
+        _ => {}
+    }
+}
+
+fn run_action<B: VirtualDesktopBackend>(
+    context: &ActionContext<B>,
+    action: &Action,
+    follow_moved_windows: bool,
+) {
+    let backend = &context.backend;
+    // Held until the history has taken any step back or forward, so that the tracker only gets to
+    // record the resulting desktop change afterwards, when it's no longer a new visit
+    let mut history = context.history.lock();
+    let resolve = |target, history: &DesktopHistory| resolve_target(backend, history, target);
+
+    match action {
+        Action::Switch { target } => {
+            if let Some(target_index) = resolve(target, &history)
+                && switch_to_desktop(backend, target_index)
+            {
+                record_switch(&mut history, target);
+            }
+        }
+        Action::MoveWindow { target, follow } => {
+            if let Some(target_index) = resolve(target, &history) {
+                let follow = follow.unwrap_or(follow_moved_windows);
+                if move_window_to_desktop(backend, target_index, follow) {
+                    record_switch(&mut history, target);
                 }
+            }
+        }
+        Action::MoveAndFollow { target } => {
+            if let Some(target_index) = resolve(target, &history)
+                && move_window_to_desktop(backend, target_index, true)
+            {
+                record_switch(&mut history, target);
+            }
+        }
+        Action::Rename { target, name } => {
+            if let Some(target_index) = resolve(target, &history) {
+                rename_desktop(backend, target_index, name);
+            }
+        }
+        Action::Create { name, switch } => create_desktop(backend, name.as_deref(), *switch),
+        Action::Remove { target, fallback } => {
+            let Some(target_index) = resolve(target, &history) else {
+                return;
             };
-
-            if let Err(error) = hkm.register_hotkey(trigger_key, &modifier_keys, switch_lambda) {
-                return Err(ShortcutError::HotKeyRegistrationFailed(error));
+            // Relative fallbacks such as the previous desktop are taken from the removed one
+            let fallback_index = resolve_target_from(backend, &history, fallback, target_index);
+            if let Some(fallback_index) = fallback_index {
+                remove_desktop(backend, target_index, fallback_index);
             }
         }
+        Action::RunCommand { command, args } => run_command(command, args),
+        Action::OpenTaskView => send_app_action(context, AppAction::OpenTaskView),
+        Action::ReloadConfig => send_app_action(context, AppAction::ReloadConfig),
+    }
+}
 
-        for hotkey in &settings.move_window_hotkeys {
-            let (trigger_key, modifier_keys, desktop_index) =
-                guard_clause!(hotkey.parse(), error, {
-                    return Err(ShortcutError::InvalidKey(error));
-                });
-
-            let target_index = desktop_index.clone() as usize;
-            let follow_moved_windows = settings.follow_moved_windows.clone();
-
-            let switch_lambda = move || {
-                let WindowInfo {
-                    id: target_hwnd, ..
-                } = guard_clause!(get_active_window(), error, {
-                    log::error!("Failed to get current active window: {:?}", error);
-                    return;
-                });
-
-                let target_window = HWND(target_hwnd as *mut c_void);
-
-                let current_desktops = guard_clause!(get_desktops(), error, {
-                    log::error!("Failed to get desktops: {:?}", error);
-                    return;
-                });
-
-                match current_desktops.get(target_index) {
-                    None => log::error!(
-                        "Desktop index not found while attempting to move window: {}",
-                        target_index,
-                    ),
-                    Some(&target_desktop) => {
-                        if let Err(error) = move_window_to_desktop(target_desktop, &target_window) {
-                            log::error!("Failed to switch desktop: {:?}", error);
-                        }
-                        if !follow_moved_windows {
-                            return;
-                        }
-                        if let Err(error) = switch_desktop(target_desktop) {
-                            log::error!("Failed to switch desktop: {:?}", error);
-                        }
-                    }
-                }
+/// Feeds the key presses that are expected right now to the matcher, and only keeps those from
+/// other applications. Keys continuing a sequence, like a bare `3`, are thus only taken while that
+/// sequence is pending. Matched actions are sent to `actions`, as the hook can't wait for them.
+fn key_handler(
+    mut matcher: SequenceMatcher<KeyCombo, Action>,
+    actions: Sender<Action>,
+) -> KeyHandler {
+    Box::new(move |vk_code, is_down| {
+        let now = Instant::now();
+        if matcher.expire(now) {
+            log::info!("Key sequence timed out");
+        }
+        let Some(combo) = matcher
+            .expected_keys()
+            .into_iter()
+            .find(|combo| combo.is_pressed(vk_code, is_down))
+        else {
+            return false;
+        };
+        if let SequenceStep::Matched(action) = matcher.press(combo, now)
+            && actions.send(action).is_err()
+        {
+            log::error!("Could not run hotkey action, the hotkey thread has stopped");
+        }
+        true
+    })
+}
+
+/// Leaves out the bindings with a key press that `check` rejects, as that key belongs to another
+/// program, and returns a failure for every such key combination.
+fn keep_available(
+    bindings: Vec<Binding>,
+    check: impl Fn(&KeyCombo) -> Result<(), RegistrationError>,
+) -> (Vec<Binding>, Vec<RegistrationFailure>) {
+    let mut failures: Vec<RegistrationFailure> = vec![];
+    let mut checked: Vec<KeyCombo> = vec![];
+    for combo in bindings.iter().flat_map(|binding| &binding.keys) {
+        if checked.contains(combo) {
+            continue;
+        }
+        checked.push(combo.clone());
+        if let Err(error) = check(combo) {
+            let failure = RegistrationFailure {
+                keys: combo.clone(),
+                error,
             };
+            log::warn!("Failed to register hotkey {}", failure);
+            failures.push(failure);
+        }
+    }
 
-            if let Err(error) = hkm.register_hotkey(trigger_key, &modifier_keys, switch_lambda) {
-                return Err(ShortcutError::HotKeyRegistrationFailed(error));
-            }
+    let available = bindings
+        .into_iter()
+        .filter(|binding| {
+            binding
+                .keys
+                .iter()
+                .all(|combo| failures.iter().all(|failure| failure.keys != *combo))
+        })
+        .collect();
+    (available, failures)
+}
+
+impl ShortcutHandler {
+    /// Checks every key of every hotkey up front, including those continuing a sequence, so the
+    /// failures stay the same for as long as the handler lives.
+    pub fn new<B: VirtualDesktopBackend>(
+        settings: &Settings,
+        context: &ActionContext<B>,
+    ) -> Result<Self, ShortcutError> {
+        let mut bindings = vec![];
+        for hotkey in &settings.hotkeys {
+            let hotkey_bindings = guard_clause!(hotkey.bindings(), error, {
+                return Err(ShortcutError::InvalidKey(error));
+            });
+            bindings.extend(hotkey_bindings);
         }
+        let (bindings, failures) = keep_available(bindings, check_available);
 
-        // Focus first window on desktop change to fix wrong input focus after desktop switch
-        thread::spawn(move || {
-            desktop_event_hooks.on_active_desktop_change(|_| {
-                let open_windows = guard_clause!(get_open_windows(), error, {
-                    log::error!("Failed to get open windows: {:?}", error);
-                    return;
-                });
-
-                let target_window = {
-                    let window_on_desktop = open_windows.into_iter().find(|window| {
-                        // Minimized windows have negative coordinates
-                        // Full screen windows also have negative coordinates (presumably padding?)
-                        if !window.position.is_full_screen
-                            && (window.position.x <= 0 || window.position.y <= 0)
-                        {
-                            return false;
-                        }
-                        let window_handle = HWND(window.id as *mut c_void);
-                        is_window_on_current_desktop(window_handle).unwrap_or(false)
-                    });
-                    let Some(target_window) = window_on_desktop else {
-                        // Expected - Desktop probably has no open windows
-                        return;
-                    };
-                    target_window
-                };
-
-                // Weird calling semantics...
-                if unsafe { SetForegroundWindow(HWND(target_window.id as *mut c_void)).0 } == 0 {
-                    log::error!("Failed to set active window");
-                    return;
-                }
+        let mut matcher = SequenceMatcher::new(Duration::from_millis(settings.sequence_timeout_ms));
+        for binding in bindings {
+            matcher.add(binding.keys, binding.action);
+        }
 
-                log::info!("Set active window: {:?}", target_window);
+        let (actions, action_receiver) = mpsc::channel::<Action>();
+        let _action_thread = {
+            let context = context.clone();
+            let follow_moved_windows = settings.follow_moved_windows;
+            // Ends along with the key handler, which holds the only sender
+            thread::spawn(move || {
+                for action in action_receiver {
+                    run_action(&context, &action, follow_moved_windows);
+                }
             })
-        });
+        };
+        set_key_handler(Some(key_handler(matcher, actions)));
 
-        thread::spawn(move || {
-            hkm.event_loop();
-        });
+        Ok(Self { failures })
+    }
 
-        Ok(handler)
+    /// Hotkeys that couldn't be registered, while the other hotkeys keep working.
+    pub fn failures(&self) -> &[RegistrationFailure] {
+        &self.failures
     }
 
     pub fn terminate(&self) {
-        self.interrupt_handle.interrupt();
+        set_key_handler(None);
+    }
+}
+
+/// Focuses the first window on every desktop change to fix wrong input focus after a switch.
+pub fn spawn_focus_fix<B: VirtualDesktopBackend>(desktop_event_hooks: DesktopEventHooks<B>) {
+    thread::spawn(move || {
+        desktop_event_hooks.on_active_desktop_change(|_| {
+            if let Err(error) = desktop_event_hooks
+                .backend()
+                .focus_window_on_current_desktop()
+            {
+                log::error!("Failed to set active window: {:?}", error);
+            }
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::FakeBackend;
+    use std::sync::mpsc::{self, Receiver};
+
+    fn context(desktop_names: &[&str]) -> (ActionContext<FakeBackend>, Receiver<AppAction>) {
+        let (app_actions, app_action_receiver) = mpsc::channel();
+        let context = ActionContext {
+            backend: FakeBackend::new(desktop_names),
+            history: SharedHistory::new(10),
+            app_actions,
+        };
+        (context, app_action_receiver)
+    }
+
+    fn run(context: &ActionContext<FakeBackend>, action: Action) {
+        run_action(context, &action, true);
+    }
+
+    fn current_name(context: &ActionContext<FakeBackend>) -> String {
+        context.backend.get_current_desktop().unwrap().name
+    }
+
+    #[test]
+    fn switches_to_the_target() {
+        let (context, _) = context(&["A", "B", "C"]);
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Name("C".to_string()),
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Next { wrap: false },
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Next { wrap: true },
+            },
+        );
+        assert_eq!(current_name(&context), "A");
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Previous { wrap: true },
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Index(7),
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+    }
+
+    #[test]
+    fn steps_through_the_history_only_after_switching() {
+        let (context, _) = context(&["A", "B", "C"]);
+        for index in 0..3 {
+            context.backend.switch_desktop(index).unwrap();
+            context.history.lock().visit(context.backend.get_current_desktop().unwrap());
+        }
+        let back = Action::Switch {
+            target: DesktopTarget::Back,
+        };
+
+        context.backend.state().fail_switches = true;
+        let before = context.history.lock().clone();
+        run(&context, back.clone());
+        assert_eq!(*context.history.lock(), before);
+
+        context.backend.state().fail_switches = false;
+        run(&context, back.clone());
+        assert_eq!(current_name(&context), "B");
+        run(&context, back);
+        assert_eq!(current_name(&context), "A");
+        run(
+            &context,
+            Action::Switch {
+                target: DesktopTarget::Forward,
+            },
+        );
+        assert_eq!(current_name(&context), "B");
+
+        // Sending a window ahead without following it takes no step
+        let forward = DesktopTarget::Forward;
+        run(
+            &context,
+            Action::MoveWindow {
+                target: forward.clone(),
+                follow: Some(false),
+            },
+        );
+        run(&context, Action::MoveAndFollow { target: forward });
+        assert_eq!(current_name(&context), "C");
+        assert_eq!(context.backend.state().window_moves, [2, 2]);
+    }
+
+    #[test]
+    fn moves_windows_with_or_without_following() {
+        let (context, _) = context(&["A", "B", "C"]);
+        let target = DesktopTarget::Index(1);
+        run_action(
+            &context,
+            &Action::MoveWindow {
+                target: target.clone(),
+                follow: None,
+            },
+            false,
+        );
+        assert_eq!(current_name(&context), "A");
+        run_action(
+            &context,
+            &Action::MoveWindow {
+                target: target.clone(),
+                follow: Some(true),
+            },
+            false,
+        );
+        assert_eq!(current_name(&context), "B");
+        run(
+            &context,
+            Action::MoveAndFollow {
+                target: DesktopTarget::Last,
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+        run(
+            &context,
+            Action::MoveWindow {
+                target,
+                follow: Some(false),
+            },
+        );
+        assert_eq!(current_name(&context), "C");
+        assert_eq!(context.backend.state().window_moves, [1, 1, 2, 1]);
+    }
+
+    #[test]
+    fn renames_creates_and_removes_desktops() {
+        let (context, _) = context(&["A", "B"]);
+        run(
+            &context,
+            Action::Rename {
+                target: DesktopTarget::Index(1),
+                name: "Work".to_string(),
+            },
+        );
+        run(
+            &context,
+            Action::Create {
+                name: Some("New".to_string()),
+                switch: true,
+            },
+        );
+        assert_eq!(current_name(&context), "New");
+        let names =
+            |context: &ActionContext<FakeBackend>| context.backend.state().desktop_names.clone();
+        assert_eq!(names(&context), ["A", "Work", "New"]);
+
+        // The current desktop goes, and its fallback is the desktop before it
+        let remove_current = Action::Remove {
+            target: DesktopTarget::Current,
+            fallback: DesktopTarget::Previous { wrap: true },
+        };
+        run(&context, remove_current.clone());
+        assert_eq!(names(&context), ["A", "Work"]);
+        assert_eq!(current_name(&context), "Work");
+
+        run(
+            &context,
+            Action::Remove {
+                target: DesktopTarget::First,
+                fallback: DesktopTarget::Last,
+            },
+        );
+        assert_eq!(names(&context), ["Work"]);
+        assert_eq!(current_name(&context), "Work");
+        // The last desktop is its own fallback
+        run(&context, remove_current);
+        assert_eq!(names(&context), ["Work"]);
+    }
+
+    #[test]
+    fn only_takes_continuation_keys_while_a_sequence_is_pending() {
+        let mut matcher = SequenceMatcher::new(Duration::from_secs(60));
+        let keys = |texts: &[&str]| texts.iter().map(|text| text.parse().unwrap()).collect();
+        matcher.add(keys(&["Alt+D", "3"]), Action::ReloadConfig);
+        let (actions, action_receiver) = mpsc::channel();
+        let mut handler = key_handler(matcher, actions);
+        let mut press = |text: &str| {
+            let combo: KeyCombo = text.parse().unwrap();
+            let mut held: Vec<_> = combo.modifier_keys().iter().map(|key| key.vk_code).collect();
+            held.push(combo.trigger_key().vk_code);
+            handler(combo.trigger_key().vk_code, &|code| held.contains(&code))
+        };
+
+        assert!(!press("3"));
+        assert!(!press("Alt+3"));
+        assert!(press("Alt+D"));
+        assert!(press("3"));
+        assert!(!press("3"));
+        assert_eq!(
+            action_receiver.try_iter().collect::<Vec<_>>(),
+            [Action::ReloadConfig]
+        );
+    }
+
+    #[test]
+    fn leaves_out_hotkeys_with_unavailable_keys() {
+        let binding = |texts: &[&str], action| Binding {
+            keys: texts.iter().map(|text| text.parse().unwrap()).collect(),
+            action,
+        };
+        let bindings = vec![
+            binding(&["Alt+1"], Action::OpenTaskView),
+            binding(&["Alt+D", "1"], Action::ReloadConfig),
+            binding(&["Alt+E", "1"], Action::OpenTaskView),
+            binding(&["Alt+D", "2"], Action::ReloadConfig),
+        ];
+        let taken: KeyCombo = "Alt+D".parse().unwrap();
+        let (available, failures) = keep_available(bindings.clone(), |combo| {
+            if *combo == taken {
+                Err(RegistrationError::TakenByOtherProgram)
+            } else {
+                Ok(())
+            }
+        });
+
+        assert_eq!(available, [bindings[0].clone(), bindings[2].clone()]);
+        // Reported once, however many hotkeys start with it
+        let failures: Vec<_> = failures.iter().map(|it| it.to_string()).collect();
+        assert_eq!(failures, ["Alt+D (used by another program)"]);
+    }
+
+    #[test]
+    fn forwards_app_actions() {
+        let (context, app_actions) = context(&["A"]);
+        run(&context, Action::OpenTaskView);
+        run(&context, Action::ReloadConfig);
+        assert_eq!(
+            app_actions.try_iter().collect::<Vec<_>>(),
+            [AppAction::OpenTaskView, AppAction::ReloadConfig]
+        );
     }
 }
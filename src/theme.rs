@@ -0,0 +1,210 @@
+use std::thread;
+#[cfg(not(windows))]
+use std::time::Duration;
+
+/// How often the system theme is checked where there is no way to be told about changes.
+#[cfg(not(windows))]
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+#[allow(unused)]
+enum ThemeError {
+    /// The system has no such setting, so asking again won't help.
+    Unsupported,
+    /// The setting couldn't be read this time.
+    Unreadable,
+}
+
+#[cfg(windows)]
+const PERSONALIZE_KEY: windows::core::PCWSTR =
+    windows::core::w!(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize");
+
+#[cfg(windows)]
+fn read_system_theme() -> Result<bool, ThemeError> {
+    use std::ffi::c_void;
+    use windows::core::w;
+    use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+    let mut uses_light_theme: u32 = 0;
+    let mut size = size_of::<u32>() as u32;
+    // The taskbar follows the system theme, while apps have their own `AppsUseLightTheme`
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            PERSONALIZE_KEY,
+            w!("SystemUsesLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut uses_light_theme as *mut u32 as *mut c_void),
+            Some(&mut size),
+        )
+    };
+    match result {
+        ERROR_SUCCESS => Ok(uses_light_theme == 0),
+        // Versions before Windows 10 1903 have no light taskbar
+        ERROR_FILE_NOT_FOUND => Err(ThemeError::Unsupported),
+        _ => Err(ThemeError::Unreadable),
+    }
+}
+
+/// Only desktops that follow the freedesktop color scheme setting, like GNOME, are recognized.
+#[cfg(target_os = "linux")]
+fn read_system_theme() -> Result<bool, ThemeError> {
+    let output = std::process::Command::new("gsettings")
+        .args(["get", "org.gnome.desktop.interface", "color-scheme"])
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ThemeError::Unsupported)
+        }
+        Err(_) => return Err(ThemeError::Unreadable),
+    };
+    // Fails for GNOME versions without the setting
+    if !output.status.success() {
+        return Err(ThemeError::Unsupported);
+    }
+    let color_scheme = String::from_utf8_lossy(&output.stdout);
+    Ok(is_dark_color_scheme(&color_scheme))
+}
+
+/// `'prefer-dark'` is dark, while `'default'` and `'prefer-light'` are not.
+#[cfg(any(test, target_os = "linux"))]
+fn is_dark_color_scheme(color_scheme: &str) -> bool {
+    color_scheme.trim().trim_matches('\'') == "prefer-dark"
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn read_system_theme() -> Result<bool, ThemeError> {
+    Err(ThemeError::Unsupported)
+}
+
+/// Whether the taskbar is in dark mode, or `None` if that can't be told.
+pub fn is_system_dark() -> Option<bool> {
+    read_system_theme().ok()
+}
+
+/// Calls `on_change` with the new mode whenever the system switches between light and dark mode.
+#[cfg(windows)]
+pub fn spawn_theme_watcher(on_change: impl Fn(bool) + Send + 'static) {
+    use windows::Win32::Foundation::{BOOL, ERROR_SUCCESS, HANDLE};
+    use windows::Win32::System::Registry::{
+        RegCloseKey, RegNotifyChangeKeyValue, RegOpenKeyExW, HKEY, HKEY_CURRENT_USER, KEY_NOTIFY,
+        REG_NOTIFY_CHANGE_LAST_SET,
+    };
+
+    thread::spawn(move || {
+        let mut key = HKEY::default();
+        let result =
+            unsafe { RegOpenKeyExW(HKEY_CURRENT_USER, PERSONALIZE_KEY, 0, KEY_NOTIFY, &mut key) };
+        if result != ERROR_SUCCESS {
+            log::error!("Could not watch the system theme: {:?}", result);
+            return;
+        }
+
+        let mut dark = read_system_theme();
+        loop {
+            // Blocks until a value of the key is written, and only reports that one change
+            let result = unsafe {
+                RegNotifyChangeKeyValue(
+                    key,
+                    BOOL::from(false),
+                    REG_NOTIFY_CHANGE_LAST_SET,
+                    HANDLE::default(),
+                    BOOL::from(false),
+                )
+            };
+            if result != ERROR_SUCCESS {
+                log::error!("Stopped watching the system theme: {:?}", result);
+                break;
+            }
+            report_change(&mut dark, read_system_theme(), &on_change);
+        }
+        unsafe {
+            let _ = RegCloseKey(key);
+        }
+    });
+}
+
+/// Calls `on_change` with the new mode whenever the system switches between light and dark mode.
+#[cfg(not(windows))]
+pub fn spawn_theme_watcher(on_change: impl Fn(bool) + Send + 'static) {
+    thread::spawn(move || poll_theme(read_system_theme, on_change, thread::sleep));
+}
+
+/// Reads the theme every `POLL_INTERVAL` until the system turns out to have no such setting.
+#[cfg(not(windows))]
+fn poll_theme(
+    mut read: impl FnMut() -> Result<bool, ThemeError>,
+    on_change: impl Fn(bool),
+    mut sleep: impl FnMut(Duration),
+) {
+    let mut dark = read();
+    while dark != Err(ThemeError::Unsupported) {
+        sleep(POLL_INTERVAL);
+        report_change(&mut dark, read(), &on_change);
+    }
+    log::info!("The system has no light or dark mode to follow");
+}
+
+fn report_change(
+    dark: &mut Result<bool, ThemeError>,
+    now_dark: Result<bool, ThemeError>,
+    on_change: impl Fn(bool),
+) {
+    if now_dark == *dark {
+        return;
+    }
+    *dark = now_dark;
+    if let Ok(dark) = now_dark {
+        on_change(dark);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(windows))]
+    use std::cell::RefCell;
+
+    #[test]
+    fn reads_the_color_scheme() {
+        assert!(is_dark_color_scheme("'prefer-dark'\n"));
+        assert!(!is_dark_color_scheme("'prefer-light'\n"));
+        assert!(!is_dark_color_scheme("'default'\n"));
+    }
+
+    /// Runs the poller over `readings`, returning the reported modes and how often it slept.
+    #[cfg(not(windows))]
+    fn poll(readings: &[Result<bool, ThemeError>]) -> (Vec<bool>, usize) {
+        let mut readings = readings.iter().copied();
+        let changes = RefCell::new(vec![]);
+        let mut sleeps = 0;
+        poll_theme(
+            // Running out of readings ends the test like a system without the setting
+            || readings.next().unwrap_or(Err(ThemeError::Unsupported)),
+            |dark| changes.borrow_mut().push(dark),
+            |_| sleeps += 1,
+        );
+        (changes.into_inner(), sleeps)
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn reports_each_change_of_mode() {
+        let (light, dark) = (Ok(false), Ok(true));
+        let unreadable = Err(ThemeError::Unreadable);
+        assert_eq!(poll(&[light, light, dark, dark, light]).0, [true, false]);
+        // A failed read in between doesn't count as a change, but the mode is reported again
+        assert_eq!(poll(&[dark, unreadable, dark, light]).0, [true, false]);
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn stops_polling_without_a_system_setting() {
+        assert_eq!(poll(&[Err(ThemeError::Unsupported)]), (vec![], 0));
+        let readings = [Ok(true), Err(ThemeError::Unreadable), Ok(false)];
+        assert_eq!(poll(&readings), (vec![false], 3));
+    }
+}
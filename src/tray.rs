@@ -1,8 +1,15 @@
-use crate::config::Settings;
+use crate::backend::VirtualDesktopBackend;
+use crate::config::{ConfigLocation, Settings};
 use crate::desktop::{DesktopEventHooks, DesktopInfo};
 use crate::guard_clause;
+use crate::history::{spawn_history_tracker, SharedHistory};
 use crate::icon::IconSelector;
-use crate::shortcuts::{ShortcutError, ShortcutHandler};
+use crate::shortcuts::{
+    spawn_focus_fix, ActionContext, AppAction, RegistrationFailure, ShortcutError, ShortcutHandler,
+};
+use crate::theme::{is_system_dark, spawn_theme_watcher};
+use crate::watcher::ConfigWatcher;
+use std::sync::mpsc;
 use std::{process, thread};
 use trayicon::{Error, MenuBuilder, TrayIcon, TrayIconBuilder};
 use winit::application::ApplicationHandler;
@@ -14,30 +21,41 @@ use winit::window::WindowId;
 #[derive(Clone, Eq, PartialEq, Debug)]
 enum Event {
     ActiveDesktopChanged(DesktopInfo),
+    DesktopsChanged(Vec<DesktopInfo>),
+    ConfigFileChanged,
     LeftClick,
+    AppActionRequested(AppAction),
+    SystemThemeChanged(bool),
     Exit,
 }
 
-pub struct TrayApp {
+pub struct TrayApp<B: VirtualDesktopBackend> {
+    settings: Settings,
+    config_location: ConfigLocation,
     tray_icon: TrayIcon<Event>,
     icon_selector: IconSelector,
-    desktop_event_hooks: DesktopEventHooks,
+    system_dark: bool,
+    current_desktop: Option<DesktopInfo>,
+    desktops: Vec<DesktopInfo>,
+    desktop_event_hooks: DesktopEventHooks<B>,
     shortcut_handler: ShortcutHandler,
+    action_context: ActionContext<B>,
+    _config_watcher: Option<ConfigWatcher>,
 }
 
 #[derive(Debug)]
-#[allow(unused)]
+#[allow(unused, clippy::enum_variant_names)]
 pub enum TrayAppError {
     EventLoopError(EventLoopError),
     ShortcutHandlerError(ShortcutError),
     TrayIconBuildError(Error),
-    MissingDefaultIcon,
 }
 
-impl TrayApp {
+impl<B: VirtualDesktopBackend> TrayApp<B> {
     pub fn start(
-        settings: &Settings,
-        desktop_event_hooks: DesktopEventHooks,
+        settings: Settings,
+        config_location: ConfigLocation,
+        desktop_event_hooks: DesktopEventHooks<B>,
     ) -> Result<(), TrayAppError> {
         let event_loop = guard_clause!(EventLoop::<Event>::with_user_event().build(), error, {
             return Err(TrayAppError::EventLoopError(error));
@@ -45,11 +63,10 @@ impl TrayApp {
         event_loop.set_control_flow(ControlFlow::Wait);
 
         let proxy = event_loop.create_proxy();
-        let icon_selector = IconSelector::new(settings);
+        let system_dark = is_system_dark().unwrap_or(false);
+        let icon_selector = IconSelector::new(&settings, system_dark);
 
-        let Some(default_icon) = icon_selector.get_default() else {
-            return Err(TrayAppError::MissingDefaultIcon);
-        };
+        let default_icon = icon_selector.get_default();
 
         let tray_icon = guard_clause!(
             TrayIconBuilder::new()
@@ -61,7 +78,7 @@ impl TrayApp {
                 .icon(default_icon.as_ref().clone())
                 .tooltip("DesktopIndicator")
                 .on_click(Event::LeftClick)
-                .menu(MenuBuilder::new().item("Exit", Event::Exit))
+                .menu(build_menu(&[]))
                 .build(),
             error,
             {
@@ -69,66 +86,222 @@ impl TrayApp {
             }
         );
 
-        let shortcut_handler = guard_clause!(
-            ShortcutHandler::new(settings, desktop_event_hooks.clone()),
-            error,
-            {
+        let history = SharedHistory::new(settings.history_depth);
+        spawn_history_tracker(desktop_event_hooks.clone(), history.clone());
+
+        let (app_actions, app_action_receiver) = mpsc::channel::<AppAction>();
+        let _app_action_thread = {
+            let proxy = event_loop.create_proxy();
+            thread::spawn(move || {
+                for action in app_action_receiver {
+                    if proxy.send_event(Event::AppActionRequested(action)).is_err() {
+                        return;
+                    }
+                }
+            })
+        };
+
+        let action_context = ActionContext {
+            backend: desktop_event_hooks.backend().clone(),
+            history,
+            app_actions,
+        };
+        let shortcut_handler =
+            guard_clause!(ShortcutHandler::new(&settings, &action_context), error, {
                 return Err(TrayAppError::ShortcutHandlerError(error));
-            }
-        );
+            });
+
+        spawn_focus_fix(desktop_event_hooks.clone());
+
+        let config_watcher = {
+            let proxy = event_loop.create_proxy();
+            let watcher = ConfigWatcher::new(&config_location.paths(), move || {
+                if let Err(error) = proxy.send_event(Event::ConfigFileChanged) {
+                    log::error!("Failed to send config change event: {}", error);
+                }
+            });
+            // Not fatal, the app just won't pick up config changes until restarted
+            watcher
+                .map_err(|error| log::error!("Failed to watch config file: {:?}", error))
+                .ok()
+        };
 
         let mut app = TrayApp {
+            settings,
+            config_location,
             tray_icon,
             icon_selector,
+            system_dark,
+            current_desktop: None,
+            desktops: vec![],
             desktop_event_hooks: desktop_event_hooks.clone(),
             shortcut_handler,
+            action_context,
+            _config_watcher: config_watcher,
         };
 
+        let _desktops_thread = {
+            let proxy = event_loop.create_proxy();
+            let desktop_event_hooks = desktop_event_hooks.clone();
+            thread::spawn(move || {
+                desktop_event_hooks.on_desktops_change(|desktops: Vec<DesktopInfo>| {
+                    if let Err(error) = proxy.send_event(Event::DesktopsChanged(desktops)) {
+                        log::error!("Failed to send desktops change event: {}", error);
+                    }
+                });
+            })
+        };
+
+        {
+            let proxy = event_loop.create_proxy();
+            spawn_theme_watcher(move |dark| {
+                if let Err(error) = proxy.send_event(Event::SystemThemeChanged(dark)) {
+                    log::error!("Failed to send system theme change event: {}", error);
+                }
+            });
+        }
+
         let _thread = {
             let proxy = event_loop.create_proxy();
             thread::spawn(move || {
                 desktop_event_hooks.on_active_desktop_change(|info: DesktopInfo| {
-                    if let Err(_) = proxy.send_event(Event::ActiveDesktopChanged(info)) {
-                        return;
+                    if let Err(error) = proxy.send_event(Event::ActiveDesktopChanged(info)) {
+                        log::error!("Failed to send active desktop change event: {}", error);
                     }
                 });
             })
         };
 
+        app.update_menu();
+        app.update_tooltip();
+
         if let Err(error) = event_loop.run_app(&mut app) {
             return Err(TrayAppError::EventLoopError(error));
         };
         Ok(())
     }
+
+    /// Shows the name and position of the current desktop, e.g. "Work (2/4)", and how many hotkeys
+    /// couldn't be registered.
+    fn update_tooltip(&mut self) {
+        let mut tooltip = match &self.current_desktop {
+            Some(info) if !self.desktops.is_empty() => format!(
+                "DesktopIndicator - {} ({}/{})",
+                info.name,
+                info.index + 1,
+                self.desktops.len()
+            ),
+            _ => "DesktopIndicator".to_string(),
+        };
+        let failure_count = self.shortcut_handler.failures().len();
+        if failure_count > 0 {
+            tooltip.push_str(&format!("\n{} hotkeys unavailable", failure_count));
+        }
+        if let Err(error) = self.tray_icon.set_tooltip(&tooltip) {
+            log::error!("Failed to set tray tooltip: {}", error);
+        }
+    }
+
+    fn update_menu(&mut self) {
+        let menu = build_menu(self.shortcut_handler.failures());
+        if let Err(error) = self.tray_icon.set_menu(&menu) {
+            log::error!("Failed to set tray menu: {}", error);
+        }
+    }
+
+    fn update_icon(&mut self, info: &DesktopInfo) {
+        let icon = self.icon_selector.get(info);
+
+        if let Err(error) = self.tray_icon.set_icon(icon.as_ref()) {
+            log::error!("Failed to set tray icon: {}", error);
+        }
+    }
+
+    /// Reapplies the settings files, which unless `force` is set only happens if they changed.
+    fn reload_config(&mut self, force: bool) {
+        let settings = guard_clause!(Settings::load(&self.config_location), error, {
+            log::error!(
+                "Error while reloading settings, keeping the previous configuration: {}",
+                error
+            );
+            return;
+        });
+        if !force && settings == self.settings {
+            return;
+        }
+
+        // Hotkeys of both handlers would fire if they were registered side by side
+        self.shortcut_handler.terminate();
+        match ShortcutHandler::new(&settings, &self.action_context) {
+            Ok(shortcut_handler) => self.shortcut_handler = shortcut_handler,
+            Err(error) => {
+                log::error!(
+                    "Error while applying reloaded hotkeys, keeping the previous configuration: {:?}",
+                    error
+                );
+                match ShortcutHandler::new(&self.settings, &self.action_context) {
+                    Ok(shortcut_handler) => self.shortcut_handler = shortcut_handler,
+                    Err(error) => {
+                        log::error!("Failed to restore the previous hotkeys: {:?}", error)
+                    }
+                }
+                return;
+            }
+        }
+        self.icon_selector = IconSelector::new(&settings, self.system_dark);
+        self.action_context
+            .history
+            .lock()
+            .set_depth(settings.history_depth);
+        self.settings = settings;
+        self.update_menu();
+        self.update_tooltip();
+
+        match self.desktop_event_hooks.backend().get_current_desktop() {
+            Ok(info) => self.update_icon(&info),
+            Err(error) => log::error!("Could not get current desktop: {:?}", error),
+        }
+        log::info!(
+            "Reloaded settings from {}",
+            self.config_location.primary.display()
+        );
+    }
 }
 
-impl ApplicationHandler<Event> for TrayApp {
+impl<B: VirtualDesktopBackend> ApplicationHandler<Event> for TrayApp<B> {
     fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: Event) {
         match event {
             Event::ActiveDesktopChanged(info) => {
-                let Some(icon) = self
-                    .icon_selector
-                    .get_by_index(info.index)
-                    .or(self.icon_selector.get_default())
-                else {
-                    log::error!("Failed to select tray icon (Perhaps no default was set?)");
-                    return;
-                };
-
-                if let Err(error) = self.tray_icon.set_icon(icon.as_ref()) {
-                    log::error!("Failed to set tray icon: {}", error);
+                self.update_icon(&info);
+                self.current_desktop = Some(info);
+                self.update_tooltip();
+            }
+            Event::DesktopsChanged(desktops) => {
+                // Generated icons may show the name of the current desktop, which could have changed
+                let current_desktop = self
+                    .current_desktop
+                    .as_ref()
+                    .and_then(|current| desktops.iter().find(|info| info.index == current.index));
+                if let Some(info) = current_desktop.cloned() {
+                    self.update_icon(&info);
+                    self.current_desktop = Some(info);
                 }
+                self.desktops = desktops;
+                self.update_tooltip();
             }
-            Event::LeftClick => {
-                // https://stackoverflow.com/a/79009385/10661599
-                if let Err(error) = process::Command::new("explorer")
-                    .arg("shell:::{3080F90E-D7AD-11D9-BD98-0000947B0257}")
-                    .spawn()
-                {
-                    log::error!("Could not open task view: {}", error);
-                };
+            Event::ConfigFileChanged => self.reload_config(false),
+            Event::LeftClick | Event::AppActionRequested(AppAction::OpenTaskView) => {
+                open_task_view()
+            }
+            Event::AppActionRequested(AppAction::ReloadConfig) => self.reload_config(true),
+            Event::SystemThemeChanged(dark) => {
+                self.system_dark = dark;
+                self.icon_selector = IconSelector::new(&self.settings, dark);
+                if let Some(info) = self.current_desktop.clone() {
+                    self.update_icon(&info);
+                }
             }
             Event::Exit => {
                 self.shortcut_handler.terminate();
@@ -144,13 +317,43 @@ impl ApplicationHandler<Event> for TrayApp {
         _window_id: WindowId,
         event: WindowEvent,
     ) {
-        match event {
-            WindowEvent::CloseRequested => {
-                self.shortcut_handler.terminate();
-                self.desktop_event_hooks.terminate();
-                event_loop.exit();
-            }
-            _ => {}
+        if let WindowEvent::CloseRequested = event {
+            self.shortcut_handler.terminate();
+            self.desktop_event_hooks.terminate();
+            event_loop.exit();
         }
     }
 }
+
+/// Lists the hotkeys that couldn't be registered above "Exit". Clicking any of them reloads the
+/// settings, which checks again whether another program registered the same keys.
+fn build_menu(failures: &[RegistrationFailure]) -> MenuBuilder<Event> {
+    let menu = MenuBuilder::new();
+    if failures.is_empty() {
+        return menu.item("Exit", Event::Exit);
+    }
+
+    let retry = Event::AppActionRequested(AppAction::ReloadConfig);
+    let mut unavailable = MenuBuilder::new();
+    for failure in failures {
+        unavailable = unavailable.item(&failure.to_string(), retry.clone());
+    }
+    let unavailable = unavailable.separator().item("Check again", retry);
+
+    menu.submenu(
+        &format!("Unavailable hotkeys ({})", failures.len()),
+        unavailable,
+    )
+    .separator()
+    .item("Exit", Event::Exit)
+}
+
+fn open_task_view() {
+    // https://stackoverflow.com/a/79009385/10661599
+    if let Err(error) = process::Command::new("explorer")
+        .arg("shell:::{3080F90E-D7AD-11D9-BD98-0000947B0257}")
+        .spawn()
+    {
+        log::error!("Could not open task view: {}", error);
+    };
+}
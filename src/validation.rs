@@ -0,0 +1,547 @@
+use crate::config::{
+    Action, DesktopPattern, DesktopTarget, GeneratedIcons, HotKey, IconRule, IconSet, Settings,
+};
+use crate::guard_clause;
+use crate::icon::{check_icon_buffer, DesktopMatcher};
+use crate::keys::{find_key, parse_combo_range, KeyCombo, KeyDefinition, KeyError};
+use crate::render::{to_ico, Color};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::fs;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// The app still starts, e.g. falling back to the default icon for an unreadable icon file.
+    Warning,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct ValidationIssue {
+    /// Location of the offending field, e.g. `hotkeys[1].trigger_key`.
+    pub path: String,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "{}: {}", self.path, self.message),
+            Severity::Warning => write!(f, "{}: warning: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Checks the parts of the settings that deserialization alone can't, returning every problem found.
+pub fn validate(settings: &Settings) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    validate_hotkeys(settings, &mut issues);
+    validate_icons(settings, &mut issues);
+    issues
+}
+
+fn parse_key(
+    name: &str,
+    path: String,
+    issues: &mut Vec<ValidationIssue>,
+) -> Option<&'static KeyDefinition> {
+    let key = find_key(name);
+    if key.is_none() {
+        issues.push(ValidationIssue {
+            path,
+            message: KeyError::UnknownKey(name.to_string()).to_string(),
+            severity: Severity::Error,
+        });
+    }
+    key
+}
+
+/// Checks the key names of one key press, reporting every invalid one.
+fn check_chord(
+    modifier_keys: &[String],
+    trigger_key: &str,
+    path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) -> bool {
+    let mut valid = parse_key(trigger_key, format!("{}.trigger_key", path), issues).is_some();
+    for (modifier_index, modifier_key) in modifier_keys.iter().enumerate() {
+        let modifier_path = format!("{}.modifier_keys[{}]", path, modifier_index);
+        valid &= parse_key(modifier_key, modifier_path, issues).is_some();
+    }
+    valid
+}
+
+/// Checks the keys of the first key press, which are either in `keys` or in `modifier_keys` and
+/// `trigger_key`.
+fn check_first_chord(hotkey: &HotKey, path: &str, issues: &mut Vec<ValidationIssue>) -> bool {
+    let Some(keys) = &hotkey.keys else {
+        if hotkey.trigger_key.is_empty() {
+            issues.push(ValidationIssue {
+                path: path.to_string(),
+                message: "needs either `keys` or a `trigger_key`".to_string(),
+                severity: Severity::Error,
+            });
+            return false;
+        }
+        return check_chord(&hotkey.modifier_keys, &hotkey.trigger_key, path, issues);
+    };
+
+    let keys_path = format!("{}.keys", path);
+    if !hotkey.modifier_keys.is_empty() || !hotkey.trigger_key.is_empty() {
+        issues.push(ValidationIssue {
+            path: keys_path,
+            message: "can't be combined with `modifier_keys` and `trigger_key`".to_string(),
+            severity: Severity::Error,
+        });
+        return false;
+    }
+    let combos = guard_clause!(parse_combo_range(keys), error, {
+        issues.push(ValidationIssue {
+            path: keys_path,
+            message: error.to_string(),
+            severity: Severity::Error,
+        });
+        return false;
+    });
+
+    let is_range = combos.iter().any(|(_, digit)| digit.is_some());
+    match hotkey.action.target() {
+        // Every digit would do the same thing
+        None if is_range => {
+            issues.push(ValidationIssue {
+                path: keys_path,
+                message: format!(
+                    "digit range in '{}' needs an action with a target desktop",
+                    keys
+                ),
+                severity: Severity::Error,
+            });
+            return false;
+        }
+        Some(target) if is_range && *target != DesktopTarget::Current => {
+            issues.push(ValidationIssue {
+                path: format!("{}.action.target", path),
+                message: format!("is set by the digit range in '{}'", keys),
+                severity: Severity::Error,
+            })
+        }
+        _ => {}
+    }
+    true
+}
+
+fn validate_hotkeys(settings: &Settings, issues: &mut Vec<ValidationIssue>) {
+    // Each valid key sequence along with the path of its hotkey
+    let mut sequences: Vec<(Vec<KeyCombo>, String)> = vec![];
+
+    for (index, hotkey) in settings.hotkeys.iter().enumerate() {
+        let path = format!("hotkeys[{}]", index);
+        validate_action(&hotkey.action, format!("{}.action", path), issues);
+
+        let mut valid = check_first_chord(hotkey, &path, issues);
+        for (then_index, chord) in hotkey.then.iter().enumerate() {
+            let chord_path = format!("{}.then[{}]", path, then_index);
+            valid &= check_chord(
+                &chord.modifier_keys,
+                &chord.trigger_key,
+                &chord_path,
+                issues,
+            );
+        }
+        let bindings = match hotkey.bindings() {
+            Ok(bindings) if valid => bindings,
+            _ => continue,
+        };
+
+        for binding in bindings {
+            // The matcher acts on the shorter sequence as soon as it is complete
+            let conflict = sequences.iter().find_map(|(other, other_path)| {
+                if *other == binding.keys {
+                    Some(format!("duplicates the key combination of {}", other_path))
+                } else if binding.keys.starts_with(other) {
+                    Some(format!(
+                        "starts with the whole key sequence of {}, so it can never be completed",
+                        other_path
+                    ))
+                } else if other.starts_with(&binding.keys) {
+                    Some(format!(
+                        "is the start of the key sequence of {}, which could then never be completed",
+                        other_path
+                    ))
+                } else {
+                    None
+                }
+            });
+            // One conflict per hotkey is enough, even if a digit range has several
+            if let Some(message) = conflict {
+                issues.push(ValidationIssue {
+                    path,
+                    message,
+                    severity: Severity::Error,
+                });
+                break;
+            }
+            sequences.push((binding.keys, path.clone()));
+        }
+    }
+}
+
+fn validate_action(action: &Action, path: String, issues: &mut Vec<ValidationIssue>) {
+    if let Some(target) = action.target() {
+        validate_target(target, format!("{}.target", path), issues);
+    }
+    if let Action::Remove { fallback, .. } = action {
+        validate_target(fallback, format!("{}.fallback", path), issues);
+    }
+    let (path, message) = match action {
+        Action::Rename { name, .. }
+        | Action::Create {
+            name: Some(name), ..
+        } if name.is_empty() => (format!("{}.name", path), "new desktop name is empty"),
+        Action::RunCommand { command, .. } if command.is_empty() => {
+            (format!("{}.command", path), "command is empty")
+        }
+        _ => return,
+    };
+    issues.push(ValidationIssue {
+        path,
+        message: message.to_string(),
+        severity: Severity::Error,
+    });
+}
+
+/// Accepts `01234567-89ab-cdef-0123-456789abcdef`, optionally wrapped in braces.
+fn is_guid(guid: &str) -> bool {
+    let guid = guid
+        .strip_prefix('{')
+        .and_then(|it| it.strip_suffix('}'))
+        .unwrap_or(guid);
+    guid.len() == 36
+        && guid.char_indices().all(|(index, char)| match index {
+            8 | 13 | 18 | 23 => char == '-',
+            _ => char.is_ascii_hexdigit(),
+        })
+}
+
+// Whether the desktop exists can only be known when the hotkey is pressed
+fn validate_target(target: &DesktopTarget, path: String, issues: &mut Vec<ValidationIssue>) {
+    let (path, message) = match target {
+        DesktopTarget::Name(name) if name.is_empty() => (
+            format!("{}.name", path),
+            "desktop name is empty".to_string(),
+        ),
+        DesktopTarget::Guid(guid) if !is_guid(guid) => (
+            format!("{}.guid", path),
+            format!("'{}' is not a valid GUID", guid),
+        ),
+        _ => return,
+    };
+    issues.push(ValidationIssue {
+        path,
+        message,
+        severity: Severity::Error,
+    });
+}
+
+fn validate_icon(icon_path: &str, path: String, issues: &mut Vec<ValidationIssue>) {
+    let message = match fs::read(icon_path).map(to_ico) {
+        Ok(Ok(buffer)) => match check_icon_buffer(&buffer) {
+            Ok(()) => return,
+            Err(error) => format!("'{}' is not a valid icon: {}", icon_path, error),
+        },
+        Ok(Err(error)) => format!("'{}' is not a valid icon: {}", icon_path, error),
+        Err(error) => format!("could not read icon file '{}': {}", icon_path, error),
+    };
+    issues.push(ValidationIssue {
+        path,
+        message,
+        severity: Severity::Warning,
+    });
+}
+
+fn validate_icons(settings: &Settings, issues: &mut Vec<ValidationIssue>) {
+    let icons = &settings.icons;
+    let plain = IconSet {
+        default: icons.default.clone(),
+        desktops: icons.desktops.clone(),
+        rules: icons.rules.clone(),
+        generated: icons.generated.clone(),
+    };
+    validate_icon_set(&plain, "icons", issues);
+
+    if let Some(theme) = icons
+        .theme
+        .as_ref()
+        .filter(|it| !icons.themes.contains_key(*it))
+    {
+        issues.push(ValidationIssue {
+            path: "icons.theme".to_string(),
+            message: format!("no theme named '{}' in icons.themes", theme),
+            severity: Severity::Error,
+        });
+    }
+    for (name, theme) in &icons.themes {
+        validate_icon_set(
+            &theme.light,
+            &format!("icons.themes.{}.light", name),
+            issues,
+        );
+        validate_icon_set(&theme.dark, &format!("icons.themes.{}.dark", name), issues);
+    }
+}
+
+fn validate_icon_set(icons: &IconSet, path: &str, issues: &mut Vec<ValidationIssue>) {
+    if let Some(default_icon_path) = &icons.default {
+        validate_icon(default_icon_path, format!("{}.default", path), issues);
+    }
+
+    for (index, icon_path) in &icons.desktops {
+        validate_icon(icon_path, format!("{}.desktops.{}", path, index), issues);
+    }
+
+    for (index, rule) in icons.rules.iter().enumerate() {
+        validate_icon_rule(rule, &format!("{}.rules[{}]", path, index), issues);
+    }
+
+    if let Some(generated) = &icons.generated {
+        validate_generated_icons(generated, &format!("{}.generated", path), issues);
+    }
+}
+
+fn validate_icon_rule(rule: &IconRule, path: &str, issues: &mut Vec<ValidationIssue>) {
+    validate_icon(&rule.icon, format!("{}.icon", path), issues);
+
+    let (field, message) = match &rule.desktop {
+        DesktopPattern::Guid(guid) if !is_guid(guid) => {
+            ("guid", format!("'{}' is not a valid GUID", guid))
+        }
+        DesktopPattern::Name(name) if name.is_empty() => {
+            ("name", "desktop name is empty".to_string())
+        }
+        DesktopPattern::Regex(regex) => match DesktopMatcher::new(&rule.desktop) {
+            Ok(_) => return,
+            Err(error) => {
+                // Syntax errors span several lines, pointing at the offending character
+                let error = error.to_string();
+                let reason = error.lines().last().unwrap_or_default();
+                let reason = reason.trim_start_matches("error: ");
+                (
+                    "regex",
+                    format!("'{}' is not a valid regex: {}", regex, reason),
+                )
+            }
+        },
+        _ => return,
+    };
+    issues.push(ValidationIssue {
+        path: format!("{}.{}", path, field),
+        message,
+        severity: Severity::Error,
+    });
+}
+
+fn validate_generated_icons(
+    generated: &GeneratedIcons,
+    path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    if !(1..=32).contains(&generated.font_size) {
+        issues.push(ValidationIssue {
+            path: format!("{}.font_size", path),
+            message: format!("{} is not between 1 and 32", generated.font_size),
+            severity: Severity::Error,
+        });
+    }
+    for (field, color) in [
+        ("foreground", &generated.foreground),
+        ("background", &generated.background),
+    ] {
+        if Color::parse(color).is_none() {
+            issues.push(ValidationIssue {
+                path: format!("{}.{}", path, field),
+                message: format!("'{}' is not a color like #RRGGBB", color),
+                severity: Severity::Error,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use std::path::Path;
+
+    fn settings(yaml: &str) -> Settings {
+        Config::builder()
+            .add_source(File::from_str(yaml, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    fn issues(yaml: &str) -> Vec<(String, String)> {
+        validate(&settings(yaml))
+            .into_iter()
+            .map(|issue| (issue.path, issue.message))
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_problem_with_its_path() {
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("hotkeys: [{ keys: Alt+1, action: { type: switch } }]", &[]),
+            (
+                r#"
+hotkeys:
+  - { modifier_keys: [Alt, Hyper], trigger_key: Nope, action: { type: switch } }
+  - { keys: Alt+D, then: [{ modifier_keys: [Meta], trigger_key: "1" }], action: { type: switch } }
+  - { action: { type: rename, name: "" } }
+icons:
+  theme: missing
+  generated: { font_size: 40, foreground: red }
+"#,
+                &[
+                    ("hotkeys[0].trigger_key", "'Nope' is not a valid key name"),
+                    (
+                        "hotkeys[0].modifier_keys[1]",
+                        "'Hyper' is not a valid key name",
+                    ),
+                    (
+                        "hotkeys[1].then[0].modifier_keys[0]",
+                        "'Meta' is not a valid key name",
+                    ),
+                    ("hotkeys[2].action.name", "new desktop name is empty"),
+                    ("hotkeys[2]", "needs either `keys` or a `trigger_key`"),
+                    ("icons.generated.font_size", "40 is not between 1 and 32"),
+                    (
+                        "icons.generated.foreground",
+                        "'red' is not a color like #RRGGBB",
+                    ),
+                    ("icons.theme", "no theme named 'missing' in icons.themes"),
+                ],
+            ),
+            (
+                r#"
+hotkeys:
+  - { keys: Alt+1-3, action: { type: switch, target: { index: 1 } } }
+  - { keys: Alt+D, trigger_key: D, action: { type: switch } }
+  - { keys: Alt+0-2, action: { type: switch } }
+  - { keys: Alt+1-9, action: { type: create } }
+  - { keys: Ctrl+1-2, action: { type: reload-config } }
+"#,
+                &[
+                    (
+                        "hotkeys[0].action.target",
+                        "is set by the digit range in 'Alt+1-3'",
+                    ),
+                    (
+                        "hotkeys[1].keys",
+                        "can't be combined with `modifier_keys` and `trigger_key`",
+                    ),
+                    (
+                        "hotkeys[2].keys",
+                        "'0-2' is not a range of digits within 1-9",
+                    ),
+                    (
+                        "hotkeys[3].keys",
+                        "digit range in 'Alt+1-9' needs an action with a target desktop",
+                    ),
+                    (
+                        "hotkeys[4].keys",
+                        "digit range in 'Ctrl+1-2' needs an action with a target desktop",
+                    ),
+                ],
+            ),
+        ];
+
+        for (yaml, expected) in cases {
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(path, message)| (path.to_string(), message.to_string()))
+                .collect();
+            assert_eq!(issues(yaml), expected, "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn reports_conflicting_key_sequences() {
+        let cases = [
+            (
+                "[{ keys: Alt+D, action: { type: switch } }, { keys: alt + d, action: { type: open-task-view } }]",
+                "duplicates the key combination of hotkeys[0]",
+            ),
+            (
+                "[{ keys: Alt+2, action: { type: switch } }, { keys: Alt+1-3, action: { type: switch } }]",
+                "duplicates the key combination of hotkeys[0]",
+            ),
+            (
+                r#"[{ keys: Alt+D, action: { type: switch } },
+                    { keys: Alt+D, then: [{ trigger_key: "1" }], action: { type: switch } }]"#,
+                "starts with the whole key sequence of hotkeys[0], so it can never be completed",
+            ),
+            (
+                r#"[{ keys: Alt+D, then: [{ trigger_key: "1" }], action: { type: switch } },
+                    { keys: Alt+D, action: { type: switch } }]"#,
+                "is the start of the key sequence of hotkeys[0], which could then never be completed",
+            ),
+        ];
+
+        for (hotkeys, message) in cases {
+            assert_eq!(
+                issues(&format!("hotkeys: {}", hotkeys)),
+                [("hotkeys[1]".to_string(), message.to_string())],
+                "{}",
+                hotkeys
+            );
+        }
+
+        let unrelated = r#"
+hotkeys:
+  - { keys: Alt+D, then: [{ trigger_key: "1" }], action: { type: switch } }
+  - { keys: Alt+D, then: [{ trigger_key: "2" }], action: { type: switch } }
+  - { keys: Ctrl+Alt+D, action: { type: switch } }
+"#;
+        assert_eq!(issues(unrelated), []);
+    }
+
+    #[test]
+    fn only_warns_about_icon_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_an_image = dir.path().join("not-an-image.png");
+        fs::write(&not_an_image, "not an image").unwrap();
+        let missing = dir.path().join("missing.png");
+        let path = |path: &Path| path.display().to_string().replace('\\', "/");
+
+        let yaml = format!(
+            r#"
+icons:
+  default: "{}"
+  rules: [{{ name: Work, icon: "{}" }}]
+"#,
+            path(&not_an_image),
+            path(&missing)
+        );
+        let found = validate(&settings(&yaml));
+
+        let paths: Vec<_> = found.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(paths, ["icons.default", "icons.rules[0].icon"]);
+        assert!(found
+            .iter()
+            .all(|issue| issue.severity == Severity::Warning));
+        assert!(
+            found[0].message.contains("is not a valid icon"),
+            "{}",
+            found[0]
+        );
+        assert!(
+            found[1].message.starts_with("could not read icon file"),
+            "{}",
+            found[1]
+        );
+        assert!(found[0].to_string().starts_with("icons.default: warning: "));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::guard_clause;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long the settings files have to stay untouched before they are reloaded. Saving a file
+/// often changes it several times in a row, e.g. by truncating it first, or by replacing it.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl ConfigWatcher {
+    pub fn new(
+        config_paths: &[&Path],
+        on_change: impl Fn() + Send + 'static,
+    ) -> Result<Self, notify::Error> {
+        let file_names: HashSet<OsString> = config_paths
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|it| it.to_os_string())
+            .collect();
+
+        let (changes, changed) = mpsc::channel::<()>();
+        // Ends along with the watcher, which holds the only sender
+        thread::spawn(move || {
+            while changed.recv().is_ok() {
+                while changed.recv_timeout(SETTLE_TIME).is_ok() {}
+                on_change();
+            }
+        });
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = guard_clause!(event, error, {
+                log::error!("Error while watching config file: {:?}", error);
+                return;
+            });
+            // Removals are skipped, as reloading right then would only fail to find the file
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+            if event.paths.iter().any(|path| {
+                path.file_name()
+                    .is_some_and(|file_name| file_names.contains(file_name))
+            }) {
+                let _ = changes.send(());
+            }
+        })?;
+
+        // Editors commonly save by replacing the file, which would end a watch on the file itself
+        let watched_dirs: HashSet<PathBuf> = config_paths
+            .iter()
+            .map(|path| match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect();
+        for watched_dir in watched_dirs {
+            if let Err(error) = watcher.watch(&watched_dir, RecursiveMode::NonRecursive) {
+                log::error!("Failed to watch {}: {:?}", watched_dir.display(), error);
+            }
+        }
+        for config_path in config_paths {
+            log::info!("Watching for changes to {}", config_path.display());
+        }
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+
+    /// Long enough for the file system events of a change to arrive and settle.
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn count_reloads(reloads: &Receiver<()>) -> usize {
+        let mut count = 0;
+        loop {
+            // Waiting for several settle times makes sure a second reload would have happened
+            let timeout = if count == 0 { TIMEOUT } else { SETTLE_TIME * 5 };
+            match reloads.recv_timeout(timeout) {
+                Ok(()) => count += 1,
+                Err(RecvTimeoutError::Timeout) => return count,
+                Err(RecvTimeoutError::Disconnected) => panic!("Watcher stopped"),
+            }
+        }
+    }
+
+    #[test]
+    fn reloads_once_per_change_of_a_watched_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("desktop-indicator.yaml");
+        fs::write(&config_path, "history_depth: 1").unwrap();
+
+        let (reload, reloads) = mpsc::channel();
+        let _watcher = ConfigWatcher::new(&[&config_path], move || {
+            let _ = reload.send(());
+        })
+        .unwrap();
+
+        fs::write(&config_path, "history_depth: 2").unwrap();
+        assert_eq!(count_reloads(&reloads), 1, "after writing");
+
+        // How editors save atomically
+        let temp_path = dir.path().join("desktop-indicator.yaml.tmp");
+        fs::write(&temp_path, "history_depth: 3").unwrap();
+        fs::rename(&temp_path, &config_path).unwrap();
+        assert_eq!(count_reloads(&reloads), 1, "after replacing");
+    }
+
+    #[test]
+    fn ignores_other_files_in_the_same_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("desktop-indicator.yaml");
+        fs::write(&config_path, "").unwrap();
+
+        let (reload, reloads) = mpsc::channel();
+        let _watcher = ConfigWatcher::new(&[&config_path], move || {
+            let _ = reload.send(());
+        })
+        .unwrap();
+
+        let other_path = dir.path().join("other.yaml");
+        fs::write(&other_path, "history_depth: 2").unwrap();
+        fs::rename(&other_path, dir.path().join("renamed.yaml")).unwrap();
+        fs::write(dir.path().join("desktop-indicator.yaml.bak"), "").unwrap();
+        assert_eq!(count_reloads(&reloads), 0);
+    }
+}